@@ -0,0 +1,33 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::process::Command;
+
+/*
+ * Record the git commit and compiler version at build time so that they can
+ * be reported in the tempexporter_build_info metric.
+ */
+fn output(cmd: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(cmd).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let s = String::from_utf8(out.stdout).ok()?;
+    Some(s.trim().to_string())
+}
+
+fn main() {
+    let sha = output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc = output(&rustc, &["--version"])
+        .and_then(|v| v.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=TEMPEXPORTER_GIT_SHA={sha}");
+    println!("cargo:rustc-env=TEMPEXPORTER_RUSTC={rustc}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
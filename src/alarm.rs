@@ -0,0 +1,91 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use chrono::NaiveTime;
+
+use crate::config::{Config, ConfigAlarm};
+use crate::sdr::Sample;
+
+/**
+ * Alarms may be silenced by an operator for some period; e.g., while the
+ * freezer is being defrosted.  We track the time at which each silence
+ * expires.
+ */
+#[derive(Default)]
+pub struct Silences {
+    until: HashMap<String, Instant>,
+}
+
+impl Silences {
+    pub fn silence(&mut self, name: &str, until: Instant) {
+        self.until.insert(name.to_string(), until);
+    }
+
+    pub fn clear(&mut self, name: &str) {
+        self.until.remove(name);
+    }
+
+    pub fn silenced(&mut self, name: &str, now: Instant) -> bool {
+        match self.until.get(name) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                self.until.remove(name);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/**
+ * Determine whether an alarm is inside either its own quiet hours or the
+ * global quiet hours at the specified local time of day.
+ */
+pub fn quiet(config: &Config, alarm: &ConfigAlarm, t: NaiveTime) -> bool {
+    config
+        .quiet_hours
+        .iter()
+        .chain(alarm.quiet_hours.iter())
+        .any(|q| q.contains(t))
+}
+
+/**
+ * Determine whether a rate-of-change alarm should fire, given the recent
+ * temperature samples for the sensor at the alarm location.  The samples must
+ * be in the order in which they were received.
+ */
+pub fn firing(alarm: &ConfigAlarm, samples: &[Sample], now: Instant) -> bool {
+    let window = alarm.window();
+
+    let Some(latest) = samples.last() else {
+        return false;
+    };
+
+    let recent = samples
+        .iter()
+        .filter(|s| now.saturating_duration_since(s.when) <= window)
+        .map(|s| s.temperature);
+
+    let (min, max) = recent
+        .fold((latest.temperature, latest.temperature), |(min, max), t| {
+            (min.min(t), max.max(t))
+        });
+
+    if let Some(rise) = alarm.rise {
+        if latest.temperature - min > rise {
+            return true;
+        }
+    }
+
+    if let Some(fall) = alarm.fall {
+        if max - latest.temperature > fall {
+            return true;
+        }
+    }
+
+    false
+}
@@ -0,0 +1,526 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * A JSON API for programs that want the current readings without parsing the
+ * Prometheus exposition formats.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use dropshot::{
+    endpoint, HttpError, HttpResponseDeleted, HttpResponseHeaders,
+    HttpResponseOk, Path, Query, RequestContext,
+};
+use hyper::{Body, Response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slog::info;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::Config;
+use crate::sdr::Record;
+use crate::{auth, cors, ratelimit, sensor_location, Main};
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReadingsResult {
+    readings: Vec<Reading>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Reading {
+    /*
+     * The identity of the sensor, from its model, ID, and channel (e.g.,
+     * "acurite-tower-00005019-c"), as used in the configuration file:
+     */
+    sensor: String,
+    id: String,
+    model: String,
+    channel: String,
+    /*
+     * The location to which the sensor is mapped, if any:
+     */
+    location: Option<String>,
+    /*
+     * The value of each metric derived from the most recent record, by
+     * metric name:
+     */
+    values: BTreeMap<String, f64>,
+    /*
+     * The time at which rtl_433 received the record, if it reported one, and
+     * the time at which we received it:
+     */
+    time: Option<DateTime<Utc>>,
+    received: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SensorsResult {
+    sensors: Vec<SensorInfo>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Mapping {
+    Mapped,
+    Unknown,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SensorInfo {
+    sensor: String,
+    model: String,
+    mapping: Mapping,
+    location: Option<String>,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    /*
+     * The number of records received from the sensor since the exporter
+     * started:
+     */
+    messages: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct HistoryQuery {
+    location: String,
+    /*
+     * If specified, only readings observed after this time are included:
+     */
+    since: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HistoryResult {
+    location: String,
+    /*
+     * The readings for each metric, by metric name, oldest first:
+     */
+    values: BTreeMap<String, Vec<HistoryPoint>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HistoryPoint {
+    time: DateTime<Utc>,
+    value: f64,
+}
+
+/*
+ * Parquet is accepted so that a request for it is refused with a clear
+ * explanation, but writing it is not yet supported; CSV is the default.
+ */
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+/*
+ * The export body is sent in chunks of roughly this many bytes:
+ */
+const EXPORT_CHUNK: usize = 16 * 1024;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ExportQuery {
+    format: Option<ExportFormat>,
+    /*
+     * If specified, only readings from this long ago until now are included;
+     * e.g., "90m", "6h", or "2d":
+     */
+    range: Option<String>,
+}
+
+/*
+ * Parse a period of time as a number followed by a unit: seconds, minutes,
+ * hours, or days.
+ */
+fn parse_range(s: &str) -> StdResult<chrono::Duration, String> {
+    let (n, unit) =
+        s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: i64 = n.parse().map_err(|_| format!("{s:?} is not a period"))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("{s:?} does not end in s, m, h, or d")),
+    };
+    n.checked_mul(secs)
+        .filter(|secs| *secs >= 0)
+        .and_then(chrono::Duration::try_seconds)
+        .ok_or_else(|| format!("{s:?} is out of range"))
+}
+
+/*
+ * Quote a CSV field if it contains a comma, quote, or line break.
+ */
+fn csv_escape(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SensorPath {
+    sensor: String,
+}
+
+fn reading(
+    c: &Config,
+    sensor: &str,
+    r: &Record,
+    now: DateTime<Utc>,
+) -> Reading {
+    Reading {
+        sensor: sensor.to_string(),
+        id: r.label("id"),
+        model: r.model.clone(),
+        channel: r.label("channel"),
+        location: sensor_location(c, sensor).map(str::to_string),
+        values: r.metrics(c),
+        time: r.time,
+        received: now
+            - chrono::Duration::from_std(r.received.elapsed())
+                .unwrap_or_default(),
+    }
+}
+
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+endpoints! {
+    /**
+     * Every sensor from which a record has been received since the exporter
+     * started, and whether it is mapped to a location.  A new device shows up
+     * here as unknown until it is added to the configuration.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/api/v1/sensors",
+    }]
+    pub async fn sensors(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<
+        HttpResponseHeaders<HttpResponseOk<SensorsResult>>,
+        HttpError,
+    > {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let m = rc.context();
+        let c = m.config();
+
+        let sensors = m
+            .sensors
+            .seen()
+            .into_iter()
+            .map(|(sensor, s)| {
+                let location = sensor_location(&c, &sensor).map(str::to_string);
+                SensorInfo {
+                    mapping: if location.is_some() {
+                        Mapping::Mapped
+                    } else {
+                        Mapping::Unknown
+                    },
+                    location,
+                    sensor,
+                    model: s.model,
+                    first_seen: s.first,
+                    last_seen: s.last,
+                    messages: s.messages,
+                }
+            })
+            .collect();
+
+        let mut res =
+            HttpResponseHeaders::new_unnamed(HttpResponseOk(SensorsResult {
+                sensors,
+            }));
+        cors::allow(&rc, res.headers_mut());
+        Ok(res)
+    }
+
+    /**
+     * Recent readings from the sensors at a location, if the exporter has been
+     * configured to keep them; see "history_minutes".
+     */
+    #[endpoint {
+        method = GET,
+        path = "/api/v1/history",
+    }]
+    pub async fn history(
+        rc: RequestContext<Arc<Main>>,
+        query: Query<HistoryQuery>,
+    ) -> StdResult<
+        HttpResponseHeaders<HttpResponseOk<HistoryResult>>,
+        HttpError,
+    > {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let m = rc.context();
+        let c = m.config();
+        let HistoryQuery { location, since } = query.into_inner();
+
+        if c.history().is_none() {
+            return Err(HttpError::for_bad_request(
+                None,
+                "history is not enabled".to_string(),
+            ));
+        }
+
+        let mut values: BTreeMap<String, Vec<HistoryPoint>> = BTreeMap::new();
+        let mut found = false;
+        for (sensor, h) in m.sensors.history() {
+            if sensor_location(&c, &sensor) != Some(location.as_str()) {
+                continue;
+            }
+            found = true;
+
+            for e in h {
+                let time = e.time;
+                if since.is_some_and(|since| time <= since) {
+                    continue;
+                }
+
+                for (metric, value) in e.values {
+                    values
+                        .entry(metric)
+                        .or_default()
+                        .push(HistoryPoint { time, value });
+                }
+            }
+        }
+
+        if !found {
+            return Err(HttpError::for_not_found(
+                None,
+                format!("no history for location {location:?}"),
+            ));
+        }
+
+        /*
+         * If more than one sensor is mapped to the location, their readings
+         * must be interleaved.
+         */
+        for points in values.values_mut() {
+            points.sort_by_key(|p| p.time);
+        }
+
+        let mut res =
+            HttpResponseHeaders::new_unnamed(HttpResponseOk(HistoryResult {
+                location,
+                values,
+            }));
+        cors::allow(&rc, res.headers_mut());
+        Ok(res)
+    }
+
+    /**
+     * Every reading in the history buffer, one row per metric, in a form that
+     * can be loaded into a spreadsheet or data frame.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/api/v1/export",
+    }]
+    pub async fn export(
+        rc: RequestContext<Arc<Main>>,
+        query: Query<ExportQuery>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let m = rc.context();
+        let c = m.config();
+        let ExportQuery { format, range } = query.into_inner();
+
+        if c.history().is_none() {
+            return Err(HttpError::for_bad_request(
+                None,
+                "history is not enabled".to_string(),
+            ));
+        }
+        if let Some(ExportFormat::Parquet) = format {
+            return Err(HttpError::for_bad_request(
+                None,
+                "parquet export is not supported; use format=csv".to_string(),
+            ));
+        }
+        let since = match range.as_deref().map(parse_range).transpose() {
+            Ok(range) => range.map(|range| Utc::now() - range),
+            Err(e) => {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    format!("invalid range: {e}"),
+                ));
+            }
+        };
+
+        /*
+         * The rows are rendered as the body is sent, a few at a time, rather
+         * than all at once.
+         */
+        let mut rows =
+            m.sensors.history().into_iter().flat_map(move |(sensor, h)| {
+                let location = sensor_location(&c, &sensor).unwrap_or_default();
+                let sensor = csv_escape(&sensor).into_owned();
+                let location = csv_escape(location).into_owned();
+                h.into_iter()
+                    .filter(move |e| since.is_none_or(|since| e.time >= since))
+                    .map(move |e| {
+                        let time =
+                            e.time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                        let mut out = String::new();
+                        for (metric, value) in e.values {
+                            writeln!(
+                                out,
+                                "{time},{sensor},{location},{metric},{value}"
+                            )
+                            .unwrap();
+                        }
+                        out
+                    })
+            });
+        let mut header =
+            Some(String::from("time,sensor,location,metric,value\n"));
+        let chunks = std::iter::from_fn(move || {
+            let mut out = header.take().unwrap_or_default();
+            while out.len() < EXPORT_CHUNK {
+                let Some(row) = rows.next() else {
+                    break;
+                };
+                out.push_str(&row);
+            }
+            (!out.is_empty()).then_some(Ok::<_, std::convert::Infallible>(out))
+        });
+
+        let mut res = Response::builder()
+            .status(200)
+            .header("content-type", "text/csv")
+            .header(
+                "content-disposition",
+                "attachment; filename=\"tempexporter.csv\"",
+            )
+            .body(Body::wrap_stream(futures::stream::iter(chunks)))?;
+        cors::allow(&rc, res.headers_mut());
+        Ok(res)
+    }
+
+    /**
+     * Forget a sensor, so that its readings no longer appear in the output
+     * until it next transmits.
+     */
+    #[endpoint {
+        method = DELETE,
+        path = "/api/v1/sensors/{sensor}",
+    }]
+    pub async fn sensor_delete(
+        rc: RequestContext<Arc<Main>>,
+        path: Path<SensorPath>,
+    ) -> StdResult<HttpResponseDeleted, HttpError> {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+        let sensor = path.into_inner().sensor;
+
+        if !m.sensors.remove(&sensor) {
+            return Err(HttpError::for_not_found(
+                None,
+                format!("sensor {sensor:?} not found"),
+            ));
+        }
+
+        info!(log, "sensor {sensor:?} removed");
+        Ok(HttpResponseDeleted())
+    }
+
+    /**
+     * The most recent reading from each sensor.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/api/v1/readings",
+    }]
+    pub async fn readings(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<
+        HttpResponseHeaders<HttpResponseOk<ReadingsResult>>,
+        HttpError,
+    > {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let m = rc.context();
+        let c = m.config();
+        let now = Utc::now();
+
+        let readings = m
+            .sensors
+            .values()
+            .iter()
+            .map(|(sensor, r)| reading(&c, sensor, r, now))
+            .collect();
+
+        let mut res =
+            HttpResponseHeaders::new_unnamed(HttpResponseOk(ReadingsResult {
+                readings,
+            }));
+        cors::allow(&rc, res.headers_mut());
+        Ok(res)
+    }
+
+    /**
+     * A stream of server-sent events, one for each record as it is received,
+     * with the same contents as each reading from "/api/v1/readings".
+     */
+    #[endpoint {
+        method = GET,
+        path = "/api/v1/stream",
+    }]
+    pub async fn stream(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let m = Arc::clone(rc.context());
+        let rx = m.sensors.subscribe();
+
+        let events =
+            futures::stream::unfold((m, rx), |(m, mut rx)| async move {
+                let event = match tokio::time::timeout(KEEPALIVE, rx.recv())
+                    .await
+                {
+                    Ok(Ok((sensor, r))) => {
+                        let r = reading(&m.config(), &sensor, &r, Utc::now());
+                        let json = serde_json::to_string(&r).ok()?;
+                        format!("event: reading\ndata: {json}\n\n")
+                    }
+                    Ok(Err(RecvError::Lagged(n))) => {
+                        format!("event: lagged\ndata: {n}\n\n")
+                    }
+                    Ok(Err(RecvError::Closed)) => return None,
+                    /*
+                     * A comment line, which the client ignores, lets any proxy
+                     * in between know that the connection is still in use.
+                     */
+                    Err(_) => ": keepalive\n\n".to_string(),
+                };
+                Some((Ok::<_, std::convert::Infallible>(event), (m, rx)))
+            });
+
+        let mut res = Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .header("cache-control", "no-cache")
+            .body(Body::wrap_stream(events))?;
+        cors::allow(&rc, res.headers_mut());
+        Ok(res)
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Dropshot has no notion of authentication, so each endpoint that requires it
+ * must call check() before doing anything else.
+ */
+
+use std::result::Result as StdResult;
+use std::sync::Arc;
+
+use base64::Engine;
+use dropshot::{HttpError, RequestContext};
+use hyper::StatusCode;
+
+use crate::config::ConfigAuth;
+use crate::Main;
+
+/**
+ * Confirm that the request carries one of the configured credentials, if
+ * any are configured.
+ */
+pub fn check(rc: &RequestContext<Arc<Main>>) -> StdResult<(), HttpError> {
+    let c = rc.context().config();
+    let Some(auth) = &c.auth else {
+        return Ok(());
+    };
+
+    let header = rc
+        .request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok());
+    if header.is_some_and(|h| allowed(auth, h)) {
+        return Ok(());
+    }
+
+    Err(HttpError::for_client_error(
+        None,
+        StatusCode::UNAUTHORIZED,
+        "authentication required".to_string(),
+    ))
+}
+
+fn allowed(auth: &ConfigAuth, header: &str) -> bool {
+    let Some((scheme, credentials)) = header.split_once(' ') else {
+        return false;
+    };
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return auth.token.as_deref().is_some_and(|token| {
+            same(token.as_bytes(), credentials.as_bytes())
+        });
+    }
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let (Some(username), Some(password)) = (&auth.username, &auth.password)
+        else {
+            return false;
+        };
+        let Ok(decoded) =
+            base64::engine::general_purpose::STANDARD.decode(credentials)
+        else {
+            return false;
+        };
+        let expected = format!("{username}:{password}");
+        return same(expected.as_bytes(), &decoded);
+    }
+
+    false
+}
+
+/*
+ * Compare credentials in time that depends only on their length, so that
+ * the response time does not reveal how much of a guess was correct.
+ */
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
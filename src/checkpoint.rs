@@ -0,0 +1,117 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Logger};
+
+/*
+ * Positions are written out no more often than this, as a busy data file
+ * would otherwise cause a write for every read.
+ */
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/**
+ * The position within a data file up to which every record has been read,
+ * along with the identity of the file, so that we can tell whether the file
+ * has been replaced since the position was recorded.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub dev: u64,
+    pub ino: u64,
+    pub offset: u64,
+}
+
+/**
+ * Records the position reached in each data file, so that after a restart we
+ * can resume exactly where the previous process stopped.
+ */
+pub struct Checkpoint {
+    log: Logger,
+    path: PathBuf,
+    locked: Mutex<Locked>,
+}
+
+struct Locked {
+    positions: BTreeMap<PathBuf, Position>,
+    written: Option<Instant>,
+    dirty: bool,
+}
+
+impl Checkpoint {
+    pub fn load(log: Logger, path: &Path) -> Result<Checkpoint> {
+        let positions = match std::fs::read(path) {
+            Ok(buf) => match serde_json::from_slice(&buf) {
+                Ok(positions) => positions,
+                Err(e) => {
+                    warn!(log, "ignoring checkpoint {path:?}: {e}");
+                    Default::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Default::default()
+            }
+            Err(e) => bail!("read checkpoint {path:?}: {e}"),
+        };
+
+        info!(log, "loaded checkpoint {path:?}"; "files" => ?positions);
+
+        Ok(Checkpoint {
+            log,
+            path: path.to_path_buf(),
+            locked: Mutex::new(Locked {
+                positions,
+                written: None,
+                dirty: false,
+            }),
+        })
+    }
+
+    pub fn position(&self, file: &Path) -> Option<Position> {
+        self.locked.lock().unwrap().positions.get(file).copied()
+    }
+
+    /**
+     * Note the position reached in a data file, writing out the checkpoint
+     * file if it has not been written recently.
+     */
+    pub fn update(&self, file: &Path, pos: Position) {
+        let mut l = self.locked.lock().unwrap();
+        if l.positions.get(file) != Some(&pos) {
+            l.positions.insert(file.to_path_buf(), pos);
+            l.dirty = true;
+        }
+
+        if !l.dirty || l.written.is_some_and(|w| w.elapsed() < INTERVAL) {
+            return;
+        }
+
+        if let Err(e) = self.write(&l.positions) {
+            warn!(self.log, "write checkpoint {:?}: {e}", self.path);
+        }
+        l.written = Some(Instant::now());
+        l.dirty = false;
+    }
+
+    /*
+     * Write to a temporary file and rename it into place, so that the
+     * checkpoint file is never left partially written.
+     */
+    fn write(&self, positions: &BTreeMap<PathBuf, Position>) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        std::fs::write(&tmp, serde_json::to_vec(positions)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use slog::{info, warn, Logger};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::config::ConfigChild;
+use crate::privilege::Credentials;
+use crate::sdr::{Sensors, Source};
+
+/*
+ * If the child runs for at least this long before exiting, we consider it to
+ * have been healthy and restart it promptly.  Otherwise, we wait for longer
+ * and longer between attempts, up to a limit.
+ */
+const HEALTHY: Duration = Duration::from_secs(60);
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+async fn run(
+    log: &Logger,
+    c: &ConfigChild,
+    creds: Option<&Credentials>,
+    source: &Source,
+) -> Result<()> {
+    let mut cmd = Command::new(&c.command[0]);
+    cmd.args(&c.command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(creds) = creds {
+        creds.command(&mut cmd);
+    }
+    let mut child = cmd.spawn()?;
+
+    info!(log, "started {:?}", c.command; "pid" => child.id());
+    source.set_up(true);
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if stdout.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        let l = line.strip_suffix(b"\n").unwrap_or(&line);
+        source.ingest(l);
+    }
+    source.flush();
+
+    let status = child.wait().await?;
+    bail!("child exited: {status}");
+}
+
+/**
+ * Run rtl_433 ourselves, reading records from its standard output, and
+ * restart it whenever it exits.  The input source is up only while the child
+ * is running.  If we are to switch to an unprivileged user, the child always
+ * runs as that user, even if it is started before we switch.
+ */
+pub fn start(
+    log: Logger,
+    c: &ConfigChild,
+    creds: Option<Credentials>,
+    sensors: Sensors,
+) -> Source {
+    let name = format!("exec:{}", c.command[0]);
+    let source = Source::new(log.clone(), name, sensors, c.format);
+
+    let c = c.clone();
+    let source0 = source.clone();
+    tokio::spawn(async move {
+        let mut backoff = BACKOFF_MIN;
+        loop {
+            let start = Instant::now();
+            if let Err(e) = run(&log, &c, creds.as_ref(), &source0).await {
+                warn!(log, "{:?}: {e}", c.command[0]);
+            }
+            source0.set_up(false);
+
+            if start.elapsed() >= HEALTHY {
+                backoff = BACKOFF_MIN;
+            }
+            info!(log, "restarting in {backoff:?}");
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+    });
+
+    source
+}
@@ -0,0 +1,1297 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::format::Format;
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /*
+     * The size, in bytes, of the buffer used for each read from the data
+     * file.  Larger buffers speed up catching up with a large file.
+     */
+    #[serde(default = "default_read_buffer")]
+    pub read_buffer: usize,
+    /*
+     * Lines longer than this many bytes cannot be a record from rtl_433, and
+     * are discarded.
+     */
+    #[serde(default = "default_max_line")]
+    pub max_line: usize,
+    /*
+     * When we first open a data file, we begin reading this many bytes
+     * before the end so that we have a recent record from each sensor.
+     * With many sensors, some of which transmit rarely, this may need to be
+     * increased.
+     */
+    #[serde(default = "default_tail_window")]
+    pub tail_window: u64,
+    /*
+     * How often, in milliseconds, to check a data file for new records when
+     * file change notification is not available.
+     */
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /*
+     * How long, in milliseconds, to wait before opening a data file again
+     * after it has been replaced or could not be read.
+     */
+    #[serde(default = "default_reopen_delay_ms")]
+    pub reopen_delay_ms: u64,
+    /*
+     * If specified, this string (e.g., "home_") is prepended to the name of
+     * every metric we emit, so that the metrics from this exporter can be
+     * told apart from any others of the same name in a shared Prometheus.
+     */
+    #[serde(default)]
+    pub prefix: String,
+    /*
+     * Labels with a constant value (e.g., site = "house-1") to add to every
+     * sample we emit, so that the output of several exporters can be told
+     * apart without relabelling in Prometheus.
+     */
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /*
+     * If specified, each rendered response is kept for this many
+     * milliseconds and served again to any scrape in the meantime that asks
+     * for the same output, so that several Prometheus servers scraping at
+     * once share the work.  Responses served from the cache are not
+     * counted as scrapes.
+     */
+    pub cache_ms: Option<u64>,
+    /*
+     * Emit every numeric field from devices without a dedicated decoder,
+     * labelled by model, ID, channel, and field name.
+     */
+    #[serde(default)]
+    pub passthrough: bool,
+    /*
+     * Emit the heat index and the humidex, derived from the temperature and
+     * humidity, for sensors that report both.
+     */
+    #[serde(default)]
+    pub heat_index: bool,
+    #[serde(default)]
+    pub humidex: bool,
+    /*
+     * Emit histograms, for each sensor, of the signal-to-noise ratio of its
+     * messages (if rtl_433 reports it) and of the interval between them.
+     */
+    #[serde(default)]
+    pub histograms: bool,
+    /*
+     * Emit temperatures in degrees fahrenheit, either in addition to or
+     * instead of degrees celsius.
+     */
+    pub fahrenheit: Option<Fahrenheit>,
+    /*
+     * Label the samples from each sensor with the ID, model, and channel
+     * from its records, either in addition to or instead of the location.
+     * Sensors that have not been mapped to a location are then included in
+     * the output as well.
+     */
+    pub identity_labels: Option<IdentityLabels>,
+    /*
+     * The exporter is reported as ready by "/readyz" only while at least one
+     * input source is up and has produced a record within this many minutes.
+     */
+    #[serde(default = "default_ready_minutes")]
+    pub ready_minutes: u64,
+    /*
+     * If specified, the input is considered unhealthy once no input source
+     * has produced a record for this many minutes (e.g., because rtl_433 has
+     * crashed or the receiver has been unplugged), and the
+     * "tempexporter_input_healthy" gauge is reported as 0.  If "silence_exit"
+     * is also set, the exporter instead exits, so that its supervisor can
+     * restart the whole pipeline.
+     */
+    pub silence_minutes: Option<u64>,
+    #[serde(default)]
+    pub silence_exit: bool,
+    /*
+     * The minimum and maximum temperature for each sensor are tracked from
+     * when the exporter starts.  If set, they are instead reset each day at
+     * local midnight.
+     */
+    #[serde(default)]
+    pub extremes_daily: bool,
+    /*
+     * If specified, the records from each sensor over this many minutes are
+     * kept in memory and made available through "/api/v1/history".  No more
+     * than history_max records are kept for any one sensor.
+     */
+    pub history_minutes: Option<u64>,
+    #[serde(default = "default_history_max")]
+    pub history_max: usize,
+    /*
+     * Rather than discarding older records, the history may keep the average
+     * of each metric over successively longer intervals; e.g., one-minute
+     * averages after a day, and ten-minute averages after a week, until the
+     * end of the history period.
+     */
+    #[serde(default)]
+    pub downsample: Vec<ConfigDownsample>,
+    /*
+     * Include the time at which each reading was observed, rather than
+     * leaving the scrape time to stand in for it, on each sample derived from
+     * a sensor record.
+     */
+    #[serde(default)]
+    pub metric_timestamps: bool,
+    /*
+     * If specified, sensors are dropped from the output when no record has
+     * been received from them for this many minutes.
+     */
+    pub expire_minutes: Option<u64>,
+    /*
+     * If specified, the readings from a sensor are omitted from the output
+     * once no record has been received from it for this many minutes, so
+     * that Prometheus marks the series stale rather than repeating the last
+     * value.  The period may also be set for individual metric families in
+     * "stale_metrics", including the last seen time, reading age, and
+     * message count, which are otherwise always emitted so that a silent
+     * sensor can be noticed.
+     */
+    pub stale_minutes: Option<u64>,
+    #[serde(default)]
+    pub stale_metrics: BTreeMap<String, u64>,
+    /*
+     * At startup, read up to this many rotated copies of each data file
+     * (e.g., "FILE.2.gz", then "FILE.1.gz"), either compressed or not,
+     * before following the file itself.
+     */
+    #[serde(default)]
+    pub backfill: u32,
+    /*
+     * If specified, the position reached in each data file is recorded in
+     * this file, so that after a restart we resume reading exactly where we
+     * stopped.
+     */
+    pub checkpoint: Option<PathBuf>,
+    /*
+     * If specified, the most recent record from each sensor is saved to this
+     * file every minute and loaded again at startup, so that sensors do not
+     * disappear from the output until they next transmit.
+     */
+    pub state_file: Option<PathBuf>,
+    /*
+     * Alarms are not reported as firing during quiet hours, which may be
+     * specified here for all alarms or on each alarm individually.
+     */
+    pub quiet_hours: Option<QuietHours>,
+    pub spike_filter: Option<ConfigSpikeFilter>,
+    /*
+     * Receive records from an MQTT broker to which rtl_433 is publishing
+     * events (i.e., "-F mqtt"), in addition to any data files.
+     */
+    pub mqtt: Option<ConfigMqtt>,
+    /*
+     * Receive records sent by rtl_433 as syslog datagrams (i.e., "-F
+     * syslog:HOST:PORT"), in addition to any data files.
+     */
+    pub syslog: Option<ConfigSyslog>,
+    /*
+     * Receive records from the HTTP server built into rtl_433 (i.e., "-F
+     * http"), in addition to any data files.
+     */
+    pub stream: Option<ConfigStream>,
+    /*
+     * Run rtl_433 as a child process and read records from its standard
+     * output, in addition to any data files.
+     */
+    pub child: Option<ConfigChild>,
+    /*
+     * Require credentials for every request other than the "/livez" and
+     * "/readyz" probes.
+     */
+    pub auth: Option<ConfigAuth>,
+    /*
+     * Limit the rate at which each client, by IP address, may make requests
+     * of "/metrics" and the JSON API.
+     */
+    pub rate_limit: Option<ConfigRateLimit>,
+    /*
+     * Allow pages from these origins (e.g., "https://dash.example.com"), or
+     * from any origin if "*" is listed, to use the JSON API from a browser.
+     */
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    #[serde(default)]
+    pub sensor: Vec<ConfigSensor>,
+    #[serde(default)]
+    pub alarm: Vec<ConfigAlarm>,
+    #[serde(default)]
+    pub field: Vec<ConfigField>,
+    #[serde(default)]
+    pub schema: Vec<ConfigSchema>,
+}
+
+/**
+ * Describe how the fields of records from particular models map to metrics.
+ * The built-in schema (see "schema.toml") is appended to any schema entries
+ * provided in the configuration file, so that the configuration file may
+ * override the built-in handling of a model.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSchema {
+    /*
+     * Model names, as they appear in records; a name ending in "*" matches
+     * any model with that prefix.
+     */
+    pub models: Vec<String>,
+    /*
+     * Some devices send several types of message, each with a subset of the
+     * fields.  If set, fields missing from a message are carried over from
+     * the previous message from the same sensor.
+     */
+    #[serde(default)]
+    pub merge: bool,
+    pub field: Vec<ConfigField>,
+}
+
+impl ConfigSchema {
+    pub fn matches(&self, model: &str) -> bool {
+        self.models.iter().any(|m| match m.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => model == m,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct BuiltinSchema {
+    schema: Vec<ConfigSchema>,
+    field: Vec<ConfigField>,
+}
+
+fn builtin_schema() -> BuiltinSchema {
+    toml::from_str(include_str!("schema.toml"))
+        .expect("built-in schema must be valid")
+}
+
+/**
+ * Map a sensor, identified by model, ID, and channel (e.g.,
+ * "acurite-tower-00005019-c"), to a friendly location name.  Readings from
+ * sensors that do not appear in the configuration are not exposed.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSensor {
+    pub id: String,
+    pub location: String,
+    /*
+     * If specified, only the named metrics are emitted for this sensor;
+     * otherwise, every metric for which the sensor has a value is emitted.
+     */
+    pub metrics: Option<Vec<String>>,
+    /*
+     * Additional labels (e.g., room, floor, or model) for each sample from
+     * this sensor, alongside the location.
+     */
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ConfigSensor {
+    pub fn emits(&self, metric: &str) -> bool {
+        self.metrics
+            .as_ref()
+            .map(|metrics| metrics.iter().any(|m| m == metric))
+            .unwrap_or(true)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    Gauge,
+    Counter,
+}
+
+/**
+ * Map an arbitrary field from the JSON records of a particular model (or of
+ * any model, if none is specified) to a metric.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigField {
+    pub model: Option<String>,
+    /*
+     * A field name, or a dotted path to a field within nested objects:
+     */
+    pub path: String,
+    pub metric: String,
+    #[serde(rename = "type", default = "default_metric_type")]
+    pub typ: MetricType,
+    pub unit: Option<String>,
+    pub help: Option<String>,
+    /*
+     * Some devices report a running count in a field that wraps around to
+     * zero at some value.  If specified, a monotonic total is maintained for
+     * the field and emitted in place of the raw value.
+     */
+    pub wrap: Option<f64>,
+    /*
+     * Some devices report a running total that returns to zero when, for
+     * example, the batteries are changed.  If set, a monotonic total is
+     * maintained for the field and emitted in place of the raw value.
+     */
+    #[serde(default)]
+    pub resets: bool,
+    /*
+     * If specified, emit an exponentially weighted moving average of the
+     * field over approximately this many readings, rather than the raw
+     * value.  To expose both, map the field to two metrics.
+     */
+    pub smooth: Option<u32>,
+}
+
+fn default_metric_type() -> MetricType {
+    MetricType::Gauge
+}
+
+impl ConfigField {
+    pub fn matches(&self, model: &str) -> bool {
+        self.model.as_deref().map(|m| m == model).unwrap_or(true)
+    }
+
+    pub fn help(&self) -> String {
+        match (&self.help, &self.unit) {
+            (Some(help), _) => help.clone(),
+            (None, Some(unit)) => format!("{} ({unit})", self.path),
+            (None, None) => self.path.clone(),
+        }
+    }
+}
+
+pub fn valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/*
+ * Label names are as for metric names, but without colons; names that begin
+ * with "__" are reserved for use by Prometheus.
+ */
+pub fn valid_label_name(name: &str) -> bool {
+    valid_metric_name(name) && !name.contains(':') && !name.starts_with("__")
+}
+
+/*
+ * Periods are configured in minutes, but used in seconds, and added to and
+ * subtracted from times, so they must fit within the range of seconds that a
+ * time can represent.
+ */
+fn valid_minutes(minutes: u64) -> bool {
+    minutes.checked_mul(60).is_some_and(|s| i64::try_from(s).is_ok())
+}
+
+/*
+ * Periods are checked by valid_minutes() as the configuration is loaded.
+ */
+fn minutes(minutes: u64) -> std::time::Duration {
+    std::time::Duration::from_secs(minutes.saturating_mul(60))
+}
+
+fn read_secret(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(s.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => bail!("read {path:?}: {e}"),
+    }
+}
+
+fn default_read_buffer() -> usize {
+    64 * 1024
+}
+
+fn default_max_line() -> usize {
+    64 * 1024
+}
+
+fn default_tail_window() -> u64 {
+    16 * 1024
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_reopen_delay_ms() -> u64 {
+    2000
+}
+
+fn default_ready_minutes() -> u64 {
+    10
+}
+
+fn default_history_max() -> usize {
+    1000
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let b = builtin_schema();
+        Config {
+            read_buffer: default_read_buffer(),
+            max_line: default_max_line(),
+            tail_window: default_tail_window(),
+            poll_interval_ms: default_poll_interval_ms(),
+            reopen_delay_ms: default_reopen_delay_ms(),
+            prefix: String::new(),
+            labels: Default::default(),
+            cache_ms: None,
+            passthrough: false,
+            heat_index: false,
+            humidex: false,
+            histograms: false,
+            fahrenheit: None,
+            identity_labels: None,
+            ready_minutes: default_ready_minutes(),
+            silence_minutes: None,
+            silence_exit: false,
+            extremes_daily: false,
+            history_minutes: None,
+            history_max: default_history_max(),
+            downsample: Default::default(),
+            metric_timestamps: false,
+            expire_minutes: None,
+            stale_minutes: None,
+            stale_metrics: Default::default(),
+            backfill: 0,
+            checkpoint: None,
+            state_file: None,
+            quiet_hours: None,
+            spike_filter: None,
+            mqtt: None,
+            syslog: None,
+            stream: None,
+            child: None,
+            auth: None,
+            rate_limit: None,
+            cors_origins: Default::default(),
+            sensor: Default::default(),
+            alarm: Default::default(),
+            field: b.field,
+            schema: b.schema,
+        }
+    }
+}
+
+/**
+ * Sensors occasionally decode a corrupted transmission as a wildly different
+ * temperature.  A record is discarded if its temperature differs from the
+ * previous record from the same sensor by more than "delta" degrees celsius,
+ * unless the previous record is more than "seconds" seconds old.
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSpikeFilter {
+    pub delta: f64,
+    #[serde(default = "default_spike_seconds")]
+    pub seconds: u64,
+}
+
+fn default_spike_seconds() -> u64 {
+    120
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigMqtt {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    /*
+     * The topic filter to subscribe to, which may include wildcards:
+     */
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSyslog {
+    /*
+     * The address and UDP port on which to listen; e.g., "0.0.0.0:1514".
+     */
+    pub bind: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigStream {
+    /*
+     * The URL of either the line-delimited JSON stream or the event stream;
+     * e.g., "http://sdr:8433/stream" or "http://sdr:8433/events".
+     */
+    pub url: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigChild {
+    /*
+     * The program to run and its arguments, which must include "-F json" (or
+     * "-F csv", or "-F kv") for rtl_433 to write records to standard output:
+     */
+    #[serde(default = "default_child_command")]
+    pub command: Vec<String>,
+    /*
+     * The format of the records written by the program; by default, this is
+     * detected from the records themselves.
+     */
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigAuth {
+    /*
+     * A bearer token, as used by "authorization.credentials" in a Prometheus
+     * scrape configuration:
+     */
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+    /*
+     * A user name and password for basic authentication:
+     */
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    /*
+     * The token and the password may instead be read from a file, so that
+     * they need not appear in the configuration file.  Any trailing newline
+     * is ignored.  The file is read again whenever the configuration is.
+     */
+    pub token_file: Option<PathBuf>,
+    pub password_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRateLimit {
+    /*
+     * No more than this many requests in any period of this many seconds:
+     */
+    pub requests: u32,
+    #[serde(default = "default_rate_limit_seconds")]
+    pub seconds: u64,
+}
+
+fn default_rate_limit_seconds() -> u64 {
+    60
+}
+
+fn default_child_command() -> Vec<String> {
+    vec!["rtl_433".into(), "-F".into(), "json".into()]
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    "rtl_433/+/events".into()
+}
+
+fn default_mqtt_client_id() -> String {
+    "tempexporter".into()
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fahrenheit {
+    Also,
+    Instead,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityLabels {
+    Also,
+    Instead,
+}
+
+/**
+ * A daily period of local time, written as "HH:MM-HH:MM".  The period may
+ * span midnight; e.g., "22:00-07:00".
+ */
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<QuietHours> {
+        let Some((start, end)) = s.split_once('-') else {
+            bail!("quiet hours {s:?} should look like \"HH:MM-HH:MM\"");
+        };
+
+        let time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|e| anyhow!("quiet hours {s:?}: time {t:?}: {e}"))
+        };
+
+        Ok(QuietHours { start: time(start)?, end: time(end)? })
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(q: QuietHours) -> String {
+        format!("{}-{}", q.start.format("%H:%M"), q.end.format("%H:%M"))
+    }
+}
+
+impl QuietHours {
+    pub fn contains(&self, t: NaiveTime) -> bool {
+        if self.start <= self.end {
+            t >= self.start && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigDownsample {
+    /*
+     * Readings older than this many minutes are replaced by their average
+     * over each period of "interval_minutes":
+     */
+    pub after_minutes: u64,
+    pub interval_minutes: u64,
+}
+
+impl ConfigDownsample {
+    pub fn after(&self) -> std::time::Duration {
+        minutes(self.after_minutes)
+    }
+
+    pub fn interval(&self) -> std::time::Duration {
+        minutes(self.interval_minutes)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigAlarm {
+    pub name: String,
+    pub location: String,
+    /*
+     * Fire if the temperature has risen (or fallen) by more than this many
+     * degrees celsius within the last "minutes" minutes:
+     */
+    pub rise: Option<f32>,
+    pub fall: Option<f32>,
+    pub minutes: u64,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl ConfigAlarm {
+    pub fn window(&self) -> std::time::Duration {
+        minutes(self.minutes)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let s = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => bail!("read config {path:?}: {e}"),
+        };
+
+        let mut c: Config = match toml::from_str(&s) {
+            Ok(c) => c,
+            Err(e) => bail!("parse config {path:?}: {e}"),
+        };
+        let b = builtin_schema();
+        c.schema.extend(b.schema);
+        c.field.extend(b.field);
+
+        if c.read_buffer == 0 {
+            bail!("read_buffer must be greater than zero");
+        }
+        if c.max_line == 0 {
+            bail!("max_line must be greater than zero");
+        }
+        if c.poll_interval_ms == 0 {
+            bail!("poll_interval_ms must be greater than zero");
+        }
+        if !c.prefix.is_empty() && !valid_metric_name(&c.prefix) {
+            bail!("invalid metric name prefix {:?}", c.prefix);
+        }
+        if let Some(l) = c.labels.keys().find(|l| !valid_label_name(l)) {
+            bail!("invalid label name {l:?}");
+        }
+        if c.ready_minutes == 0 {
+            bail!("ready_minutes must be greater than zero");
+        }
+        if c.silence_minutes == Some(0) {
+            bail!("silence_minutes must be greater than zero");
+        }
+        if c.silence_exit && c.silence_minutes.is_none() {
+            bail!("silence_exit requires silence_minutes");
+        }
+        if c.cache_ms == Some(0) {
+            bail!("cache_ms must be greater than zero");
+        }
+        if c.history_minutes == Some(0) {
+            bail!("history_minutes must be greater than zero");
+        }
+        if c.history_max == 0 {
+            bail!("history_max must be greater than zero");
+        }
+        if !c.downsample.is_empty() && c.history_minutes.is_none() {
+            bail!("downsample requires history_minutes");
+        }
+        for (i, d) in c.downsample.iter().enumerate() {
+            if d.interval_minutes == 0 {
+                bail!("downsample interval_minutes must be greater than zero");
+            }
+            if c.history_minutes.is_some_and(|h| d.after_minutes >= h) {
+                bail!(
+                    "downsample after_minutes must be less than \
+                    history_minutes"
+                );
+            }
+            if let Some(p) = i.checked_sub(1).map(|p| &c.downsample[p]) {
+                if d.after_minutes <= p.after_minutes
+                    || d.interval_minutes <= p.interval_minutes
+                {
+                    bail!(
+                        "each downsample entry must have a greater \
+                        after_minutes and interval_minutes than the last"
+                    );
+                }
+                if d.interval_minutes % p.interval_minutes != 0 {
+                    bail!(
+                        "each downsample interval_minutes must be a \
+                        multiple of the last"
+                    );
+                }
+            }
+        }
+        let periods = [
+            ("ready_minutes", Some(c.ready_minutes)),
+            ("silence_minutes", c.silence_minutes),
+            ("history_minutes", c.history_minutes),
+            ("expire_minutes", c.expire_minutes),
+            ("stale_minutes", c.stale_minutes),
+        ];
+        for (name, m) in periods {
+            if m.is_some_and(|m| !valid_minutes(m)) {
+                bail!("{name} is too large");
+            }
+        }
+        for d in c.downsample.iter() {
+            if !valid_minutes(d.after_minutes) {
+                bail!("downsample after_minutes is too large");
+            }
+            if !valid_minutes(d.interval_minutes) {
+                bail!("downsample interval_minutes is too large");
+            }
+        }
+        if let Some((metric, _)) =
+            c.stale_metrics.iter().find(|(_, m)| !valid_minutes(**m))
+        {
+            bail!("stale_metrics for {metric:?} is too large");
+        }
+        if c.expire_minutes == Some(0) {
+            bail!("expire_minutes must be greater than zero");
+        }
+        if c.stale_minutes == Some(0) {
+            bail!("stale_minutes must be greater than zero");
+        }
+        if let Some((metric, _)) =
+            c.stale_metrics.iter().find(|(_, m)| **m == 0)
+        {
+            bail!("stale_metrics for {metric:?} must be greater than zero");
+        }
+        if let Some(mq) = &c.mqtt {
+            if mq.host.is_empty() {
+                bail!("mqtt host must not be empty");
+            }
+            if mq.username.is_none() && mq.password.is_some() {
+                bail!("mqtt password requires a username");
+            }
+        }
+        if let Some(a) = &mut c.auth {
+            if let Some(path) = &a.token_file {
+                if a.token.is_some() {
+                    bail!("auth token and token_file are mutually exclusive");
+                }
+                a.token = Some(read_secret(path)?);
+            }
+            if let Some(path) = &a.password_file {
+                if a.password.is_some() {
+                    bail!(
+                        "auth password and password_file are mutually \
+                        exclusive"
+                    );
+                }
+                a.password = Some(read_secret(path)?);
+            }
+            if a.username.is_some() != a.password.is_some() {
+                bail!("auth username and password must be specified together");
+            }
+            if a.token.is_none() && a.username.is_none() {
+                bail!("auth requires a token, or a username and password");
+            }
+            if a.token.as_deref() == Some("") {
+                bail!("auth token must not be empty");
+            }
+        }
+        if let Some(rl) = &c.rate_limit {
+            if rl.requests == 0 || rl.seconds == 0 {
+                bail!(
+                    "rate_limit requests and seconds must be greater than zero"
+                );
+            }
+        }
+        if c.child.as_ref().is_some_and(|ch| ch.command.is_empty()) {
+            bail!("child command must not be empty");
+        }
+        if let Some(sf) = &c.spike_filter {
+            if sf.delta.is_nan() || sf.delta <= 0.0 {
+                bail!("spike_filter delta must be positive");
+            }
+        }
+
+        for (i, sen) in c.sensor.iter().enumerate() {
+            if c.sensor[..i].iter().any(|o| o.id == sen.id) {
+                bail!("sensor {:?} is defined more than once", sen.id);
+            }
+            if sen.location.is_empty() {
+                bail!("sensor {:?} must have a location", sen.id);
+            }
+            for l in sen.labels.keys() {
+                if !valid_label_name(l) || l == "location" || l == "synthetic" {
+                    bail!("sensor {:?}: invalid label name {l:?}", sen.id);
+                }
+                if c.identity_labels.is_some()
+                    && ["id", "model", "channel"].contains(&l.as_str())
+                {
+                    bail!(
+                        "sensor {:?}: label {l:?} conflicts with \
+                        identity_labels",
+                        sen.id,
+                    );
+                }
+            }
+        }
+
+        for (i, a) in c.alarm.iter().enumerate() {
+            if c.alarm[..i].iter().any(|o| o.name == a.name) {
+                bail!("alarm {:?} is defined more than once", a.name);
+            }
+            if a.rise.is_none() && a.fall.is_none() {
+                bail!("alarm {:?} must specify \"rise\" or \"fall\"", a.name);
+            }
+            if a.minutes == 0 {
+                bail!("alarm {:?} must have a non-zero window", a.name);
+            }
+            if !valid_minutes(a.minutes) {
+                bail!("alarm {:?} window is too large", a.name);
+            }
+        }
+
+        for s in c.schema.iter() {
+            if s.models.is_empty() {
+                bail!("schema entries must list at least one model");
+            }
+            if let Some(f) = s.field.iter().find(|f| f.model.is_some()) {
+                bail!(
+                    "schema field {:?} for {:?} must not specify a model",
+                    f.path,
+                    s.models,
+                );
+            }
+        }
+
+        let fields = c.fields().collect::<Vec<_>>();
+        for (i, f) in fields.iter().enumerate() {
+            if !valid_metric_name(&f.metric) {
+                bail!("field {:?}: invalid metric name {:?}", f.path, f.metric);
+            }
+            if f.path.is_empty() || f.path.split('.').any(|n| n.is_empty()) {
+                bail!("field {:?}: invalid path", f.path);
+            }
+            if f.typ == MetricType::Counter && !f.metric.ends_with("_total") {
+                bail!(
+                    "field {:?}: counter {:?} must end in \"_total\"",
+                    f.path,
+                    f.metric,
+                );
+            }
+            if f.wrap.is_some_and(|w| w <= 0.0) {
+                bail!("field {:?}: wrap must be positive", f.path);
+            }
+            if f.wrap.is_some() && f.typ != MetricType::Counter {
+                bail!("field {:?}: only counters may wrap", f.path);
+            }
+            if f.resets && f.typ != MetricType::Counter {
+                bail!("field {:?}: only counters may reset", f.path);
+            }
+            if f.resets && f.wrap.is_some() {
+                bail!("field {:?}: cannot both wrap and reset", f.path);
+            }
+            if f.smooth == Some(0) {
+                bail!("field {:?}: smooth must be greater than zero", f.path);
+            }
+            if f.smooth.is_some() && f.typ != MetricType::Gauge {
+                bail!("field {:?}: only gauges may be smoothed", f.path);
+            }
+            if fields[..i]
+                .iter()
+                .any(|o| o.metric == f.metric && o.typ != f.typ)
+            {
+                bail!("metric {:?} defined with conflicting types", f.metric);
+            }
+        }
+
+        Ok(c)
+    }
+
+    pub fn sensor(&self, id: &str) -> Option<&ConfigSensor> {
+        self.sensor.iter().find(|s| s.id == id)
+    }
+
+    pub fn schema(&self, model: &str) -> Option<&ConfigSchema> {
+        self.schema.iter().find(|s| s.matches(model))
+    }
+
+    /**
+     * Every field mapping, from both the schema and the generic field list.
+     */
+    pub fn fields(&self) -> impl Iterator<Item = &ConfigField> {
+        self.schema.iter().flat_map(|s| s.field.iter()).chain(self.field.iter())
+    }
+
+    /**
+     * The field mappings that apply to records from a particular model.
+     */
+    pub fn fields_for<'a>(
+        &'a self,
+        model: &'a str,
+    ) -> impl Iterator<Item = &'a ConfigField> {
+        self.schema(model)
+            .into_iter()
+            .flat_map(|s| s.field.iter())
+            .chain(self.field.iter().filter(move |f| f.matches(model)))
+    }
+
+    /**
+     * Describe the differences between this configuration and a new one, in
+     * a form suitable for reporting to an operator.
+     */
+    pub fn diff(&self, new: &Config) -> Vec<String> {
+        let mut out = Vec::new();
+
+        for s in self.sensor.iter() {
+            match new.sensor(&s.id) {
+                None => out.push(format!("sensor {:?} removed", s.id)),
+                Some(n) if n.location != s.location => out.push(format!(
+                    "sensor {:?} moved from {:?} to {:?}",
+                    s.id, s.location, n.location,
+                )),
+                Some(n) if n != s => {
+                    out.push(format!("sensor {:?} metrics changed", s.id))
+                }
+                Some(_) => (),
+            }
+        }
+        for n in new.sensor.iter() {
+            if self.sensor(&n.id).is_none() {
+                out.push(format!(
+                    "sensor {:?} added at {:?}",
+                    n.id, n.location
+                ));
+            }
+        }
+
+        for a in self.alarm.iter() {
+            match new.alarm.iter().find(|n| n.name == a.name) {
+                None => out.push(format!("alarm {:?} removed", a.name)),
+                Some(n) if n != a => {
+                    out.push(format!("alarm {:?} changed", a.name))
+                }
+                Some(_) => (),
+            }
+        }
+        for n in new.alarm.iter() {
+            if !self.alarm.iter().any(|a| a.name == n.name) {
+                out.push(format!("alarm {:?} added", n.name));
+            }
+        }
+
+        for f in self.field.iter() {
+            if !new.field.contains(f) {
+                out.push(format!("field mapping {:?} removed", f.metric));
+            }
+        }
+        for n in new.field.iter() {
+            if !self.field.contains(n) {
+                out.push(format!("field mapping {:?} added", n.metric));
+            }
+        }
+
+        for s in self.schema.iter() {
+            match new.schema.iter().find(|n| n.models == s.models) {
+                None => out.push(format!("schema for {:?} removed", s.models)),
+                Some(n) if n != s => {
+                    out.push(format!("schema for {:?} changed", s.models))
+                }
+                Some(_) => (),
+            }
+        }
+        for n in new.schema.iter() {
+            if !self.schema.iter().any(|s| s.models == n.models) {
+                out.push(format!("schema for {:?} added", n.models));
+            }
+        }
+
+        if self.quiet_hours != new.quiet_hours {
+            out.push("global quiet hours changed".into());
+        }
+        if self.passthrough != new.passthrough {
+            out.push(format!("passthrough set to {}", new.passthrough));
+        }
+        if self.heat_index != new.heat_index {
+            out.push(format!("heat_index set to {}", new.heat_index));
+        }
+        if self.prefix != new.prefix {
+            out.push(format!(
+                "prefix changed from {:?} to {:?}",
+                self.prefix, new.prefix,
+            ));
+        }
+        if self.cache_ms != new.cache_ms {
+            out.push(format!(
+                "cache_ms changed from {:?} to {:?}",
+                self.cache_ms, new.cache_ms,
+            ));
+        }
+        if self.labels != new.labels {
+            out.push("labels changed".into());
+        }
+        if self.humidex != new.humidex {
+            out.push(format!("humidex set to {}", new.humidex));
+        }
+        if self.histograms != new.histograms {
+            out.push(format!("histograms set to {}", new.histograms));
+        }
+        if self.fahrenheit != new.fahrenheit {
+            out.push(format!(
+                "fahrenheit changed from {:?} to {:?}",
+                self.fahrenheit, new.fahrenheit,
+            ));
+        }
+        if self.identity_labels != new.identity_labels {
+            out.push(format!(
+                "identity_labels changed from {:?} to {:?}",
+                self.identity_labels, new.identity_labels,
+            ));
+        }
+        if self.extremes_daily != new.extremes_daily {
+            out.push(format!("extremes_daily set to {}", new.extremes_daily));
+        }
+        if self.history_minutes != new.history_minutes {
+            out.push(format!(
+                "history_minutes changed from {:?} to {:?}",
+                self.history_minutes, new.history_minutes,
+            ));
+        }
+        if self.history_max != new.history_max {
+            out.push(format!(
+                "history_max changed from {} to {}",
+                self.history_max, new.history_max,
+            ));
+        }
+        if self.downsample != new.downsample {
+            out.push("downsample changed".into());
+        }
+        if self.metric_timestamps != new.metric_timestamps {
+            out.push(format!(
+                "metric_timestamps set to {}",
+                new.metric_timestamps,
+            ));
+        }
+        if self.mqtt != new.mqtt {
+            out.push("mqtt changed (takes effect on restart)".into());
+        }
+        if self.syslog != new.syslog {
+            out.push("syslog changed (takes effect on restart)".into());
+        }
+        if self.stream != new.stream {
+            out.push("stream changed (takes effect on restart)".into());
+        }
+        if self.child != new.child {
+            out.push("child changed (takes effect on restart)".into());
+        }
+        if self.auth != new.auth {
+            out.push("auth changed".into());
+        }
+        if self.cors_origins != new.cors_origins {
+            out.push(format!(
+                "cors_origins changed from {:?} to {:?}",
+                self.cors_origins, new.cors_origins,
+            ));
+        }
+        if self.rate_limit != new.rate_limit {
+            out.push(format!(
+                "rate_limit changed from {:?} to {:?}",
+                self.rate_limit, new.rate_limit,
+            ));
+        }
+        if self.spike_filter != new.spike_filter {
+            out.push("spike filter changed".into());
+        }
+        if self.stale_minutes != new.stale_minutes
+            || self.stale_metrics != new.stale_metrics
+        {
+            out.push("staleness changed".into());
+        }
+        if self.expire_minutes != new.expire_minutes {
+            out.push(format!(
+                "expire_minutes changed from {:?} to {:?}",
+                self.expire_minutes, new.expire_minutes,
+            ));
+        }
+        if self.checkpoint != new.checkpoint {
+            out.push("checkpoint changed (takes effect on restart)".into());
+        }
+        if self.state_file != new.state_file {
+            out.push("state_file changed (takes effect on restart)".into());
+        }
+        if self.backfill != new.backfill {
+            out.push("backfill changed (takes effect on restart)".into());
+        }
+        if self.read_buffer != new.read_buffer {
+            out.push(
+                "read_buffer changed (takes effect when the file is reopened)"
+                    .into(),
+            );
+        }
+        if self.max_line != new.max_line {
+            out.push(
+                "max_line changed (takes effect when the file is reopened)"
+                    .into(),
+            );
+        }
+        if self.tail_window != new.tail_window {
+            out.push(
+                "tail_window changed (takes effect when the file is reopened)"
+                    .into(),
+            );
+        }
+        if self.poll_interval_ms != new.poll_interval_ms {
+            out.push(format!(
+                "poll_interval_ms changed from {} to {} (takes effect when \
+                the file is reopened)",
+                self.poll_interval_ms, new.poll_interval_ms,
+            ));
+        }
+        if self.reopen_delay_ms != new.reopen_delay_ms {
+            out.push(format!(
+                "reopen_delay_ms changed from {} to {}",
+                self.reopen_delay_ms, new.reopen_delay_ms,
+            ));
+        }
+        if self.ready_minutes != new.ready_minutes {
+            out.push(format!(
+                "ready_minutes changed from {} to {}",
+                self.ready_minutes, new.ready_minutes,
+            ));
+        }
+        if self.silence_minutes != new.silence_minutes {
+            out.push(format!(
+                "silence_minutes changed from {:?} to {:?}",
+                self.silence_minutes, new.silence_minutes,
+            ));
+        }
+        if self.silence_exit != new.silence_exit {
+            out.push(format!("silence_exit set to {}", new.silence_exit));
+        }
+
+        out
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_ms)
+    }
+
+    pub fn reopen_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.reopen_delay_ms)
+    }
+
+    pub fn ready_window(&self) -> std::time::Duration {
+        minutes(self.ready_minutes)
+    }
+
+    pub fn silence(&self) -> Option<std::time::Duration> {
+        self.silence_minutes.map(minutes)
+    }
+
+    pub fn cache_ttl(&self) -> Option<std::time::Duration> {
+        self.cache_ms.map(std::time::Duration::from_millis)
+    }
+
+    pub fn history(&self) -> Option<std::time::Duration> {
+        self.history_minutes.map(minutes)
+    }
+
+    /**
+     * How old a sensor's most recent record may be before we stop emitting
+     * samples for the nominated metric family.
+     */
+    pub fn stale(&self, metric: &str) -> Option<std::time::Duration> {
+        let m = match self.stale_metrics.get(metric) {
+            Some(m) => *m,
+            None if metric == "temperature_last_seen_timestamp_seconds"
+                || metric == "temperature_reading_age_seconds"
+                || metric == "temperature_sensor_messages_total" =>
+            {
+                return None;
+            }
+            None => self.stale_minutes?,
+        };
+        Some(minutes(m))
+    }
+
+    pub fn expire(&self) -> Option<std::time::Duration> {
+        self.expire_minutes.map(minutes)
+    }
+
+    /**
+     * How much recent history must be retained to evaluate every configured
+     * rate-of-change alarm.
+     */
+    pub fn window(&self) -> std::time::Duration {
+        minutes(self.alarm.iter().map(|a| a.minutes).max().unwrap_or(0))
+    }
+}
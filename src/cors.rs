@@ -0,0 +1,108 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Cross-origin resource sharing, so that a dashboard served from elsewhere
+ * may fetch readings from the JSON API in a browser.  The browser first asks,
+ * with an OPTIONS request, whether a request that carries credentials is
+ * allowed; dropshot requires a separate endpoint for each path, so those are
+ * generated by preflight!() below.  Credentials (e.g., HTTP authentication,
+ * or a client certificate) are allowed only from origins that are listed by
+ * name, never from any origin by "*", so that an arbitrary site cannot make
+ * requests with the credentials of a user who visits it.
+ */
+
+use std::result::Result as StdResult;
+use std::sync::Arc;
+
+use dropshot::{endpoint, HttpError, RequestContext};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Response};
+
+use crate::Main;
+
+/**
+ * Add the headers that allow a browser to share the response with the page
+ * that made the request, if that page came from an allowed origin.
+ */
+pub fn allow(rc: &RequestContext<Arc<Main>>, headers: &mut HeaderMap) {
+    let Some((origin, listed)) = allowed_origin(rc) else {
+        return;
+    };
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if listed {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+}
+
+/*
+ * Returns the origin of the request if it is allowed, and whether it is
+ * listed by name rather than allowed only by "*".
+ */
+fn allowed_origin(
+    rc: &RequestContext<Arc<Main>>,
+) -> Option<(HeaderValue, bool)> {
+    let origin = rc.request.headers().get(header::ORIGIN)?;
+    let c = rc.context().config();
+    if c.cors_origins.iter().any(|o| o.as_bytes() == origin.as_bytes()) {
+        return Some((origin.clone(), true));
+    }
+    c.cors_origins.iter().any(|o| o == "*").then(|| (origin.clone(), false))
+}
+
+fn preflight(
+    rc: &RequestContext<Arc<Main>>,
+) -> StdResult<Response<Body>, HttpError> {
+    let mut res = Response::builder().status(204).body(Body::empty())?;
+    if allowed_origin(rc).is_some() {
+        let h = res.headers_mut();
+        allow(rc, h);
+        h.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET"),
+        );
+        h.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static("authorization"),
+        );
+        h.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static("3600"),
+        );
+    }
+    Ok(res)
+}
+
+/*
+ * rustfmt does not understand the endpoint attribute within a macro, and
+ * would indent it further on each run.
+ */
+#[rustfmt::skip]
+macro_rules! preflight {
+    ($name:ident, $path:literal) => {
+        #[endpoint {
+            method = OPTIONS,
+            path = $path,
+            unpublished = true,
+        }]
+        pub async fn $name(
+            rc: RequestContext<Arc<Main>>,
+        ) -> StdResult<Response<Body>, HttpError> {
+            preflight(&rc)
+        }
+    };
+}
+
+endpoints! {
+    preflight!(readings, "/api/v1/readings");
+    preflight!(sensors, "/api/v1/sensors");
+    preflight!(stream, "/api/v1/stream");
+    preflight!(history, "/api/v1/history");
+    preflight!(export, "/api/v1/export");
+}
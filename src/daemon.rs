@@ -0,0 +1,122 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Support for running as a classic daemon, without a supervisor: we detach
+ * from the terminal into a new session, and record our process ID in a file.
+ * The original process waits until the daemon is ready, or has failed to
+ * start, so that its exit status is meaningful to whatever started it.  Until
+ * then, the daemon keeps standard error so that a problem during startup can
+ * still be seen.
+ */
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    os::fd::AsRawFd,
+    os::unix::{fs::OpenOptionsExt, net::UnixStream},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{bail, Result};
+
+/*
+ * The daemon's end of the connection to the original process.
+ */
+static PARENT: Mutex<Option<UnixStream>> = Mutex::new(None);
+
+/**
+ * Detach from the terminal.  This must be done before any other thread has
+ * been created.  Only the daemon returns; the original process exits once
+ * the daemon has called ready(), or has exited itself.
+ */
+pub fn detach() -> Result<()> {
+    let (mut parent, child) = UnixStream::pair()?;
+
+    match unsafe { libc::fork() } {
+        -1 => bail!("fork: {}", std::io::Error::last_os_error()),
+        0 => (),
+        _ => {
+            drop(child);
+            let mut buf = [0u8; 1];
+            if matches!(parent.read(&mut buf), Ok(1)) {
+                std::process::exit(0);
+            }
+            eprintln!("ERROR: daemon failed to start");
+            std::process::exit(1);
+        }
+    }
+    drop(parent);
+
+    if unsafe { libc::setsid() } == -1 {
+        bail!("setsid: {}", std::io::Error::last_os_error());
+    }
+
+    /*
+     * We remain in the current directory, as relative paths given on the
+     * command line are resolved later on.
+     */
+    redirect(&[0, 1])?;
+
+    *PARENT.lock().unwrap() = Some(child);
+    Ok(())
+}
+
+fn redirect(fds: &[libc::c_int]) -> Result<()> {
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in fds {
+        if unsafe { libc::dup2(null.as_raw_fd(), *fd) } == -1 {
+            bail!("dup2: {}", std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Let the original process know that we are ready, so that it may exit
+ * successfully.  This is a no-op if we did not detach.
+ */
+pub fn ready() -> Result<()> {
+    if let Some(mut parent) = PARENT.lock().unwrap().take() {
+        redirect(&[2])?;
+        parent.write_all(b"R")?;
+    }
+    Ok(())
+}
+
+/**
+ * Write our process ID to a file, which remains locked for as long as the
+ * returned handle is held so that a second copy cannot be started with the
+ * same file.
+ */
+pub fn pidfile(path: &Path) -> Result<File> {
+    let mut f = match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o644)
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) => bail!("open pid file {path:?}: {e}"),
+    };
+
+    let mut fl: libc::flock = unsafe { std::mem::zeroed() };
+    fl.l_type = libc::F_WRLCK as _;
+    fl.l_whence = libc::SEEK_SET as _;
+    if unsafe { libc::fcntl(f.as_raw_fd(), libc::F_SETLK, &fl) } == -1 {
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EAGAIN | libc::EACCES) => {
+                bail!("pid file {path:?} is locked; is another copy running?")
+            }
+            _ => bail!("lock pid file {path:?}: {e}"),
+        }
+    }
+
+    f.set_len(0)?;
+    writeln!(f, "{}", std::process::id())?;
+    Ok(f)
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Local, Utc};
+
+/**
+ * The current readings from the sensor at a particular location.
+ */
+pub struct Row {
+    pub location: String,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub battery_ok: Option<f64>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/**
+ * Render a page with a table of current readings, which is small enough to
+ * be legible on a phone.  Temperatures are in degrees celsius, unless
+ * fahrenheit is requested.
+ */
+pub fn render(rows: &[Row], fahrenheit: bool) -> String {
+    let now = Utc::now();
+    let unit = if fahrenheit { "&deg;F" } else { "&deg;C" };
+
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n";
+    out += "<html>\n<head>\n";
+    out += "<title>tempexporter</title>\n";
+    out += "<meta name=\"viewport\" \
+        content=\"width=device-width, initial-scale=1\">\n";
+    out += "<style>\n";
+    out += "body { font-family: sans-serif; }\n";
+    out += "td, th { padding: 0.2em 0.6em; text-align: right; }\n";
+    out += "td:first-child, th:first-child { text-align: left; }\n";
+    out += "</style>\n";
+    out += "</head>\n<body>\n";
+    out += "<table>\n";
+    writeln!(
+        out,
+        "<tr><th>Location</th><th>Temperature</th><th>Humidity</th>\
+        <th>Battery</th><th>Last Seen</th></tr>",
+    )
+    .unwrap();
+
+    for r in rows {
+        let temperature = r
+            .temperature
+            .map(|t| if fahrenheit { t * 9.0 / 5.0 + 32.0 } else { t })
+            .map(|t| format!("{t:.1} {unit}"))
+            .unwrap_or_default();
+        let humidity =
+            r.humidity.map(|h| format!("{h:.0}%")).unwrap_or_default();
+        let battery = match r.battery_ok {
+            Some(b) if b > 0.0 => "ok",
+            Some(_) => "low",
+            None => "",
+        };
+        let last_seen = r
+            .last_seen
+            .map(|t| {
+                let age = now.signed_duration_since(t).num_seconds().max(0);
+                format!(
+                    "{} ({})",
+                    t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                    ago(age),
+                )
+            })
+            .unwrap_or_default();
+
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{temperature}</td><td>{humidity}</td>\
+            <td>{battery}</td><td>{last_seen}</td></tr>",
+            escape(&r.location),
+        )
+        .unwrap();
+    }
+
+    out += "</table>\n";
+    out += "</body>\n</html>\n";
+    out
+}
+
+fn ago(secs: i64) -> String {
+    if secs < 120 {
+        format!("{secs}s ago")
+    } else if secs < 2 * 3600 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 2 * 86400 {
+        format!("{}h ago", secs / 3600)
+    } else {
+        format!("{}d ago", secs / 86400)
+    }
+}
+
+/*
+ * Locations come from the configuration file, and may contain characters
+ * that are special in HTML.
+ */
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,75 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Quantities derived from the temperature (in degrees celsius) and relative
+ * humidity (in percent) reported by a sensor.
+ */
+
+/**
+ * The US National Weather Service heat index, or "apparent temperature",
+ * using the Rothfusz regression with the adjustments described at
+ * https://www.wpc.ncep.noaa.gov/html/heatindex_equation.shtml.
+ */
+pub fn heat_index(t: f64, rh: f64) -> f64 {
+    let f = t * 9.0 / 5.0 + 32.0;
+
+    /*
+     * The simpler formula is adequate below about 80 degrees fahrenheit,
+     * where the regression does not apply.
+     */
+    let simple = 0.5 * (f + 61.0 + (f - 68.0) * 1.2 + rh * 0.094);
+    let hi = if (simple + f) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.04901523 * f + 10.14333127 * rh
+            - 0.22475541 * f * rh
+            - 0.00683783 * f * f
+            - 0.05481717 * rh * rh
+            + 0.00122874 * f * f * rh
+            + 0.00085282 * f * rh * rh
+            - 0.00000199 * f * f * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&f) {
+            hi -=
+                ((13.0 - rh) / 4.0) * ((17.0 - (f - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&f) {
+            hi += ((rh - 85.0) / 10.0) * ((87.0 - f) / 5.0);
+        }
+
+        hi
+    };
+
+    (hi - 32.0) * 5.0 / 9.0
+}
+
+/**
+ * Saturation vapour pressure of water, in hectopascals, using the Magnus
+ * formula.
+ */
+fn saturation_pressure(t: f64) -> f64 {
+    6.112 * ((17.67 * t) / (t + 243.5)).exp()
+}
+
+/**
+ * The Canadian humidex.
+ */
+pub fn humidex(t: f64, rh: f64) -> f64 {
+    let e = saturation_pressure(t) * rh / 100.0;
+    t + 0.5555 * (e - 10.0)
+}
+
+/**
+ * The mass of water vapour per unit volume of air, in grams per cubic metre.
+ */
+pub fn absolute_humidity(t: f64, rh: f64) -> f64 {
+    let e = saturation_pressure(t) * rh / 100.0;
+
+    /*
+     * The vapour pressure is converted to pascals and divided by the
+     * specific gas constant for water vapour (461.5 J/(kg K)), then scaled
+     * from kilograms to grams.
+     */
+    e * 100.0 / (461.5 * (273.15 + t)) * 1000.0
+}
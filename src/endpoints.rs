@@ -0,0 +1,479 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * The endpoints served by the HTTP server, other than those of the JSON API.
+ */
+
+use std::collections::BTreeSet;
+use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dropshot::{
+    endpoint, HttpError, HttpResponseOk, HttpResponseUpdatedNoContent, Path,
+    RequestContext, TypedBody,
+};
+use hyper::{body::Bytes, Body, Response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slog::info;
+
+use crate::{
+    auth, config, dashboard, exposition, gather, ratelimit, sensor_location,
+    AnyhowHttpError, Main,
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct AlarmPath {
+    name: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct AlarmSilence {
+    /*
+     * Silence the alarm for this many hours, or remove an existing silence if
+     * zero.
+     */
+    hours: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SyntheticReading {
+    location: String,
+    temperature: f32,
+    humidity: f32,
+    #[serde(default = "synthetic_battery_ok")]
+    battery_ok: i64,
+}
+
+fn synthetic_battery_ok() -> i64 {
+    1
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ReloadResult {
+    changes: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct HealthResult {
+    uptime_seconds: f64,
+    sources: Vec<HealthSource>,
+    sensors: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct HealthSource {
+    name: String,
+    up: bool,
+    lines: u64,
+}
+
+endpoints! {
+    #[endpoint {
+        method = GET,
+        path = "/metrics",
+    }]
+    pub async fn metrics(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        ratelimit::check(&rc)?;
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+        let c = m.config();
+
+        /*
+         * A scraper may ask for only some metric families, by name; e.g.,
+         * "/metrics?name[]=temperature_degrees_celsius".
+         */
+        let names = rc
+            .request
+            .uri()
+            .query()
+            .map(|q| {
+                form_urlencoded::parse(q.as_bytes())
+                    .filter(|(k, _)| k == "name[]")
+                    .map(|(_, v)| v.into_owned())
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default();
+
+        let headers = rc.request.headers();
+        let x = exposition::Exposition::from_accept(
+            headers.get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok()),
+        );
+        let gzip = exposition::accepts_gzip(
+            headers
+                .get(hyper::header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+        );
+
+        let mut res = Response::builder()
+            .status(200)
+            .header("content-type", x.content_type())
+            .header("vary", "accept, accept-encoding");
+        if gzip {
+            res = res.header("content-encoding", "gzip");
+        }
+
+        let Some(ttl) = c.cache_ttl() else {
+            return Ok(res.body(gather(log, m, &c, &names).into_body(x, gzip))?);
+        };
+
+        /*
+         * If caching is enabled, the rendered body is kept for a short time and
+         * served to any other scrape that asks for the same output.  Concurrent
+         * scrapes wait for and share the one render rather than each starting
+         * their own; the cache lock itself is held only to find the entry.
+         */
+        let cell = {
+            let now = Instant::now();
+            let mut cache = m.cache.lock().unwrap();
+            cache.retain(|_, (when, _)| {
+                now.saturating_duration_since(*when) < ttl
+            });
+            let (_, cell) = cache
+                .entry((x, gzip, names.clone()))
+                .or_insert_with(|| (now, Default::default()));
+            Arc::clone(cell)
+        };
+        let body = cell
+            .get_or_init(|| async {
+                let e = gather(log, m, &c, &names);
+                Bytes::from(
+                    e.into_chunks(x, gzip).flatten().collect::<Vec<_>>(),
+                )
+            })
+            .await;
+        Ok(res.body(Body::from(body.clone()))?)
+    }
+
+    #[endpoint {
+        method = POST,
+        path = "/alarms/{name}/silence",
+    }]
+    pub async fn alarm_silence(
+        rc: RequestContext<Arc<Main>>,
+        path: Path<AlarmPath>,
+        body: TypedBody<AlarmSilence>,
+    ) -> StdResult<HttpResponseUpdatedNoContent, HttpError> {
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+        let name = path.into_inner().name;
+        let hours = body.into_inner().hours;
+
+        if !m.config().alarm.iter().any(|a| a.name == name) {
+            return Err(HttpError::for_not_found(
+                None,
+                format!("alarm {name:?} not found"),
+            ));
+        }
+
+        let mut silences = m.silences.lock().unwrap();
+        if hours == 0 {
+            info!(log, "alarm {name:?} silence removed");
+            silences.clear(&name);
+        } else {
+            let Some(until) = hours.checked_mul(3600).and_then(|s| {
+                Instant::now().checked_add(Duration::from_secs(s))
+            }) else {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    format!("cannot silence alarm for {hours} hours"),
+                ));
+            };
+            info!(log, "alarm {name:?} silenced for {hours} hours");
+            silences.silence(&name, until);
+        }
+
+        Ok(HttpResponseUpdatedNoContent())
+    }
+
+    /*
+     * Inject a reading as if it had been received from a sensor at the
+     * specified location.  The reading is treated like any other, so that
+     * dashboards and alarms can be tested, but is labelled as synthetic in the
+     * metrics output.
+     */
+    #[endpoint {
+        method = POST,
+        path = "/inject",
+    }]
+    pub async fn inject(
+        rc: RequestContext<Arc<Main>>,
+        body: TypedBody<SyntheticReading>,
+    ) -> StdResult<HttpResponseUpdatedNoContent, HttpError> {
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+        let b = body.into_inner();
+
+        if b.location.is_empty() {
+            return Err(HttpError::for_bad_request(
+                None,
+                "location must not be empty".into(),
+            ));
+        }
+
+        info!(log, "injecting synthetic reading for {:?}", b.location);
+        m.sensors.inject(&b.location, b.temperature, b.humidity, b.battery_ok);
+
+        Ok(HttpResponseUpdatedNoContent())
+    }
+
+    /**
+     * Forget the minimum and maximum temperatures received so far, and begin
+     * tracking them again from the next reading.
+     */
+    #[endpoint {
+        method = POST,
+        path = "/extremes/reset",
+    }]
+    pub async fn extremes_reset(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<HttpResponseUpdatedNoContent, HttpError> {
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+
+        info!(log, "resetting temperature extremes");
+        m.sensors.reset_extremes();
+
+        Ok(HttpResponseUpdatedNoContent())
+    }
+
+    #[endpoint {
+        method = POST,
+        path = "/reload",
+    }]
+    pub async fn reload(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<HttpResponseOk<ReloadResult>, HttpError> {
+        auth::check(&rc)?;
+        let log = &rc.log;
+        let m = rc.context();
+
+        let changes = m.reload().or_400()?;
+        info!(log, "configuration reloaded"; "changes" => ?changes);
+
+        Ok(HttpResponseOk(ReloadResult { changes }))
+    }
+
+    /**
+     * The configuration currently in effect, including the built-in schema, but
+     * without any credentials.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/config",
+    }]
+    pub async fn config_get(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        auth::check(&rc)?;
+        let m = rc.context();
+
+        let mut body = serde_json::to_vec_pretty(&*m.config())
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+        body.push(b'\n');
+        Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(body.into())?)
+    }
+
+    /**
+     * A brief summary of the state of the exporter, which is much cheaper to
+     * produce than the full set of metrics.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/health",
+    }]
+    pub async fn health(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<HttpResponseOk<HealthResult>, HttpError> {
+        auth::check(&rc)?;
+        let m = rc.context();
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let sources = m
+            .sources()
+            .iter()
+            .map(|s| HealthSource {
+                name: s.name().to_string(),
+                up: s.up(),
+                lines: s.stats().lines,
+            })
+            .collect();
+
+        Ok(HttpResponseOk(HealthResult {
+            uptime_seconds: (now - m.start_time).max(0.0),
+            sources,
+            sensors: m.sensors.values().len(),
+        }))
+    }
+
+    /**
+     * A page for anybody who points a browser at the exporter, with links to
+     * the endpoints that can be viewed there.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/",
+    }]
+    pub async fn index(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        auth::check(&rc)?;
+        let body = concat!(
+            "<!DOCTYPE html>\n",
+            "<html>\n",
+            "<head><title>tempexporter</title></head>\n",
+            "<body>\n",
+            "<h1>tempexporter</h1>\n",
+            "<p>Prometheus exporter for rtl_433 sensor readings, version ",
+            env!("CARGO_PKG_VERSION"),
+            ".</p>\n",
+            "<ul>\n",
+            "<li><a href=\"/metrics\">/metrics</a></li>\n",
+            "<li><a href=\"/dashboard\">/dashboard</a></li>\n",
+            "<li><a href=\"/health\">/health</a></li>\n",
+            "<li><a href=\"/api/v1/readings\">/api/v1/readings</a></li>\n",
+            "<li><a href=\"/api/v1/sensors\">/api/v1/sensors</a></li>\n",
+            "<li><a href=\"/api/v1/stream\">/api/v1/stream</a></li>\n",
+            "<li><a href=\"/livez\">/livez</a></li>\n",
+            "<li><a href=\"/readyz\">/readyz</a></li>\n",
+            "<li><a href=\"/openapi.json\">/openapi.json</a></li>\n",
+            "</ul>\n",
+            "</body>\n",
+            "</html>\n",
+        );
+
+        Ok(Response::builder()
+            .status(200)
+            .header("content-type", "text/html; charset=utf-8")
+            .body(body.into())?)
+    }
+
+    /**
+     * A table of the current readings at each location.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/dashboard",
+    }]
+    pub async fn dashboard_page(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        auth::check(&rc)?;
+        let m = rc.context();
+        let c = m.config();
+
+        /*
+         * The values are those that appear in the metrics output, as mapped
+         * from the fields of each record by the schema.
+         */
+        let mut rows = m
+            .sensors
+            .values()
+            .iter()
+            .filter_map(|(id, r)| {
+                let location = sensor_location(&c, id)?;
+                let metric = |name: &str| {
+                    c.fields_for(&r.model)
+                        .filter(|f| f.metric == name)
+                        .find_map(|f| r.value(f))
+                };
+                Some(dashboard::Row {
+                    location: location.to_string(),
+                    temperature: metric("temperature_degrees_celsius"),
+                    humidity: metric("temperature_humidity_percent"),
+                    battery_ok: metric("temperature_battery_ok"),
+                    last_seen: r.time,
+                })
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| a.location.cmp(&b.location));
+
+        let fahrenheit = c.fahrenheit == Some(config::Fahrenheit::Instead);
+        Ok(Response::builder()
+            .status(200)
+            .header("content-type", "text/html; charset=utf-8")
+            .body(dashboard::render(&rows, fahrenheit).into())?)
+    }
+
+    /**
+     * Liveness: the process is running and able to answer requests.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/livez",
+    }]
+    pub async fn livez(
+        _rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        Ok(Response::builder()
+            .status(200)
+            .header("content-type", "text/plain")
+            .body("ok\n".into())?)
+    }
+
+    /**
+     * Readiness: at least one input source is up, and has produced a record
+     * recently.  A source that is stuck, or a receiver that has stopped
+     * hearing from any sensor, makes the exporter unready rather than leaving
+     * it to serve stale readings.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/readyz",
+    }]
+    pub async fn readyz(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        let m = rc.context();
+        let window = m.config().ready_window();
+
+        let ready = m.sources().iter().any(|s| {
+            s.up()
+                && s.stats().last_record.is_some_and(|t| t.elapsed() <= window)
+        });
+
+        let (status, body) = if ready {
+            (200, "ok\n")
+        } else {
+            (503, "no recent records from any input source\n")
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header("content-type", "text/plain")
+            .body(body.into())?)
+    }
+
+    /**
+     * The OpenAPI description of this server, from which clients can be
+     * generated.
+     */
+    #[endpoint {
+        method = GET,
+        path = "/openapi.json",
+    }]
+    pub async fn openapi_description(
+        rc: RequestContext<Arc<Main>>,
+    ) -> StdResult<Response<Body>, HttpError> {
+        auth::check(&rc)?;
+        Ok(Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(rc.context().openapi.clone().into())?)
+    }
+}
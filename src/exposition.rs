@@ -0,0 +1,777 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::fmt::{self, Write};
+
+use prost::Message;
+
+/**
+ * A metric family collected by the Emitter, with all of its samples.
+ */
+pub struct Family {
+    pub name: String,
+    pub typ: Type,
+    pub desc: String,
+    pub samples: Vec<Sample>,
+}
+
+pub struct Sample {
+    pub labels: Vec<(String, String)>,
+    pub value: Point,
+    /*
+     * If provided, the timestamp is in milliseconds since the epoch.
+     */
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Type {
+    Gauge,
+    /*
+     * The value of a counter only increases, except when the exporter
+     * restarts, and the name of a counter family ends in "_total".
+     */
+    Counter,
+    Histogram,
+}
+
+impl Type {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Type::Gauge => "gauge",
+            Type::Counter => "counter",
+            Type::Histogram => "histogram",
+        }
+    }
+}
+
+/**
+ * A gauge or counter sample has a single value, while a histogram sample has
+ * a count of observations in each bucket.
+ */
+pub enum Point {
+    Scalar(Value),
+    Histogram(Histogram),
+}
+
+/**
+ * Accumulates observations into buckets with fixed upper bounds, as for a
+ * Prometheus histogram.
+ */
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /*
+     * The number of observations in each bucket (i.e., greater than the
+     * previous bound, and no greater than this one), with a final bucket for
+     * those greater than every bound:
+     */
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    /**
+     * Create an empty histogram.  The bounds must be in increasing order.
+     */
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram { bounds, counts: vec![0; bounds.len() + 1], sum: 0.0 }
+    }
+
+    pub fn observe(&mut self, v: f64) {
+        let i = self.bounds.iter().position(|b| v <= *b);
+        self.counts[i.unwrap_or(self.bounds.len())] += 1;
+        self.sum += v;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /**
+     * The upper bound of each bucket, with the cumulative count of
+     * observations no greater than that bound.  The final bucket, for
+     * which the bound is infinite, is not included.
+     */
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.bounds.iter().zip(self.counts.iter()).scan(0, |total, (b, n)| {
+            *total += n;
+            Some((*b, *total))
+        })
+    }
+}
+
+/*
+ * Sample values keep the type with which they were emitted, so that an
+ * integer (e.g., a counter) is rendered exactly rather than by way of a
+ * floating point value.
+ */
+#[derive(Clone, Copy)]
+pub enum Value {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+}
+
+impl Value {
+    fn as_f64(&self) -> f64 {
+        match self {
+            Value::I64(v) => *v as f64,
+            Value::U64(v) => *v as f64,
+            Value::F64(v) => *v,
+        }
+    }
+}
+
+/*
+ * Integers are rendered in full, and finite floating point values in the
+ * shortest form that reads back as the same value; e.g., 21.0 is "21".  Both
+ * text formats spell the values that are not finite as "NaN", "+Inf", and
+ * "-Inf", rather than as Rust would.
+ */
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I64(v) => v.fmt(f),
+            Value::U64(v) => v.fmt(f),
+            Value::F64(v) if v.is_finite() => v.fmt(f),
+            Value::F64(v) => {
+                if v.is_nan() {
+                    f.write_str("NaN")
+                } else if *v > 0.0 {
+                    f.write_str("+Inf")
+                } else {
+                    f.write_str("-Inf")
+                }
+            }
+        }
+    }
+}
+
+/**
+ * The formats in which we can render the metrics for a scrape.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Exposition {
+    /*
+     * The original Prometheus text format, version 0.0.4:
+     */
+    Text,
+    /*
+     * The OpenMetrics text format, version 1.0.0:
+     */
+    OpenMetrics,
+    /*
+     * Length-delimited MetricFamily messages in the Prometheus protocol
+     * buffer format:
+     */
+    Protobuf,
+}
+
+/*
+ * The subset of the Prometheus protocol buffer definitions (from
+ * "io/prometheus/client/metrics.proto") that we need to describe counters and
+ * gauges:
+ */
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbMetricFamily {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    help: String,
+    #[prost(int32, tag = "3")]
+    typ: i32,
+    #[prost(message, repeated, tag = "4")]
+    metric: Vec<PbMetric>,
+}
+
+const PB_COUNTER: i32 = 0;
+const PB_GAUGE: i32 = 1;
+const PB_HISTOGRAM: i32 = 4;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbMetric {
+    #[prost(message, repeated, tag = "1")]
+    label: Vec<PbLabelPair>,
+    #[prost(message, optional, tag = "2")]
+    gauge: Option<PbValue>,
+    #[prost(message, optional, tag = "3")]
+    counter: Option<PbValue>,
+    #[prost(int64, optional, tag = "6")]
+    timestamp_ms: Option<i64>,
+    #[prost(message, optional, tag = "7")]
+    histogram: Option<PbHistogram>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbHistogram {
+    #[prost(uint64, tag = "1")]
+    sample_count: u64,
+    #[prost(double, tag = "2")]
+    sample_sum: f64,
+    #[prost(message, repeated, tag = "3")]
+    bucket: Vec<PbBucket>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbBucket {
+    #[prost(uint64, tag = "1")]
+    cumulative_count: u64,
+    #[prost(double, tag = "2")]
+    upper_bound: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbLabelPair {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+/*
+ * The Gauge and Counter messages each have a single double value
+ * as their first field.
+ */
+#[derive(Clone, PartialEq, prost::Message)]
+struct PbValue {
+    #[prost(double, tag = "1")]
+    value: f64,
+}
+
+/*
+ * The approximate size of each chunk of the response body:
+ */
+const CHUNK: usize = 16 * 1024;
+
+/*
+ * OpenMetrics requires that the unit of a metric, if declared, appear as a
+ * suffix of the metric name.  We declare the unit for any metric whose name
+ * ends in one of these:
+ */
+const UNITS: &[&str] = &[
+    "seconds",
+    "bytes",
+    "celsius",
+    "fahrenheit",
+    "percent",
+    "volts",
+    "meters_per_second",
+    "millimeters",
+    "kilometers",
+    "degrees",
+    "megahertz",
+    "dbm",
+    "db",
+    "lux",
+    "grams_per_cubic_meter",
+];
+
+impl Exposition {
+    /**
+     * Choose a format based on the Accept header from the client, if any.
+     * Each media range may have a quality value; we pick the supported format
+     * with the highest, preferring the one listed first in the event of a
+     * tie, and fall back to the text format if nothing else is acceptable.
+     */
+    pub fn from_accept(accept: Option<&str>) -> Exposition {
+        let Some(accept) = accept else {
+            return Exposition::Text;
+        };
+
+        let mut best: Option<(f64, Exposition)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next().unwrap_or_default();
+
+            let mut q = 1.0;
+            let mut proto = None;
+            let mut encoding = None;
+            for p in params {
+                match p.split_once('=') {
+                    Some(("q", v)) => q = v.parse().unwrap_or(0.0),
+                    Some(("proto", v)) => proto = Some(v),
+                    Some(("encoding", v)) => encoding = Some(v),
+                    _ => (),
+                }
+            }
+
+            let x = match media {
+                "text/plain" | "*/*" => Exposition::Text,
+                "application/openmetrics-text" => Exposition::OpenMetrics,
+                "application/vnd.google.protobuf"
+                    if proto == Some("io.prometheus.client.MetricFamily")
+                        && encoding == Some("delimited") =>
+                {
+                    Exposition::Protobuf
+                }
+                _ => continue,
+            };
+
+            if q > 0.0 && best.map(|(bq, _)| q > bq).unwrap_or(true) {
+                best = Some((q, x));
+            }
+        }
+
+        best.map(|(_, x)| x).unwrap_or(Exposition::Text)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Exposition::Text => "text/plain",
+            Exposition::OpenMetrics => {
+                "application/openmetrics-text; version=1.0.0; charset=utf-8"
+            }
+            Exposition::Protobuf => {
+                "application/vnd.google.protobuf; \
+                proto=io.prometheus.client.MetricFamily; encoding=delimited"
+            }
+        }
+    }
+
+    /**
+     * Render each metric family in turn, and any trailer, producing the
+     * response body in chunks of roughly CHUNK bytes.  The text formats are
+     * written directly into the buffer for the current chunk, and small
+     * families are combined so that the client does not receive a separate
+     * chunk for each.
+     */
+    pub fn render(
+        self,
+        mut families: impl Iterator<Item = Family>,
+    ) -> impl Iterator<Item = Vec<u8>> {
+        let mut trailer = Some(self.trailer());
+
+        std::iter::from_fn(move || {
+            let mut out = Vec::new();
+            let mut text = String::new();
+            while out.len() + text.len() < CHUNK {
+                let Some(f) = families.next() else {
+                    text.extend(trailer.take());
+                    break;
+                };
+
+                match self {
+                    Exposition::Protobuf => out.extend(protobuf(&f)),
+                    Exposition::Text | Exposition::OpenMetrics => {
+                        self.family(&f, &mut text).unwrap();
+                    }
+                }
+            }
+            out.extend(text.into_bytes());
+
+            if out.is_empty() && trailer.is_none() {
+                None
+            } else {
+                Some(out)
+            }
+        })
+    }
+
+    /**
+     * Render a metric family, with its metadata and samples, in one of the
+     * text formats.  Families are rendered in the protobuf format by
+     * protobuf() instead.
+     */
+    pub fn family(&self, f: &Family, out: &mut impl Write) -> fmt::Result {
+        let (name, suffix) = match self {
+            Exposition::Protobuf => unreachable!("protobuf is not text"),
+            Exposition::Text => {
+                writeln!(out, "# TYPE {} {}", f.name, f.typ.as_str())?;
+                writeln!(out, "# HELP {} {}", f.name, escape(&f.desc, false))?;
+                (f.name.as_str(), "")
+            }
+            Exposition::OpenMetrics => {
+                /*
+                 * The name of a counter family does not include the "_total"
+                 * suffix, which appears only on the name of each sample.
+                 */
+                let (name, suffix) = match f.typ {
+                    Type::Counter => (
+                        f.name.strip_suffix("_total").unwrap_or(&f.name),
+                        "_total",
+                    ),
+                    Type::Gauge | Type::Histogram => (f.name.as_str(), ""),
+                };
+
+                writeln!(out, "# TYPE {name} {}", f.typ.as_str())?;
+                if let Some(unit) =
+                    UNITS.iter().find(|u| name.ends_with(&format!("_{u}")))
+                {
+                    writeln!(out, "# UNIT {name} {unit}")?;
+                }
+                writeln!(out, "# HELP {name} {}", escape(&f.desc, true))?;
+                (name, suffix)
+            }
+        };
+
+        for s in f.samples.iter() {
+            match &s.value {
+                Point::Scalar(v) => {
+                    self.line(out, name, suffix, s, None, v)?;
+                }
+                Point::Histogram(h) => {
+                    /*
+                     * Each bucket of a histogram is a separate line, with the
+                     * count of observations no greater than its upper bound,
+                     * followed by the count and sum of all observations.
+                     */
+                    for (le, n) in h.buckets() {
+                        let le = format!("{le:?}");
+                        self.line(out, name, "_bucket", s, Some(&le), n)?;
+                    }
+                    let n = h.count();
+                    let sum = Value::F64(h.sum());
+                    self.line(out, name, "_bucket", s, Some("+Inf"), n)?;
+                    self.line(out, name, "_count", s, None, n)?;
+                    self.line(out, name, "_sum", s, None, sum)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn line(
+        &self,
+        out: &mut impl Write,
+        name: &str,
+        suffix: &str,
+        s: &Sample,
+        le: Option<&str>,
+        value: impl fmt::Display,
+    ) -> fmt::Result {
+        write!(out, "{name}{suffix}")?;
+        labels(out, s, le)?;
+        match self {
+            Exposition::Protobuf => unreachable!("protobuf is not text"),
+            Exposition::Text => {
+                write!(out, "\t{value}")?;
+                if let Some(ts) = s.timestamp {
+                    write!(out, " {ts}")?;
+                }
+            }
+            Exposition::OpenMetrics => {
+                write!(out, " {value}")?;
+                if let Some(ts) = s.timestamp {
+                    /*
+                     * OpenMetrics timestamps are in seconds.
+                     */
+                    let sign = if ts < 0 { "-" } else { "" };
+                    let ts = ts.unsigned_abs();
+                    write!(out, " {sign}{}.{:03}", ts / 1000, ts % 1000)?;
+                }
+            }
+        }
+        out.write_char('\n')
+    }
+
+    /**
+     * Anything that must follow the last metric family.
+     */
+    pub fn trailer(&self) -> &'static str {
+        match self {
+            Exposition::Text | Exposition::Protobuf => "",
+            Exposition::OpenMetrics => "# EOF\n",
+        }
+    }
+}
+
+/**
+ * Does the Accept-Encoding header from the client, if any, allow a gzip
+ * compressed response?
+ */
+pub fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let Some(ae) = accept_encoding else {
+        return false;
+    };
+
+    ae.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        params.next().is_some_and(|c| c.eq_ignore_ascii_case("gzip"))
+            && params.all(|p| match p.split_once('=') {
+                Some(("q", v)) => v.parse::<f64>().is_ok_and(|q| q > 0.0),
+                _ => true,
+            })
+    })
+}
+
+/**
+ * Compress a sequence of chunks of the response body, producing the
+ * compressed output for each chunk as it is consumed so that the response may
+ * still be streamed.
+ */
+pub fn gzip(
+    chunks: impl Iterator<Item = Vec<u8>>,
+) -> impl Iterator<Item = Vec<u8>> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut enc = Some(GzEncoder::new(Vec::new(), Compression::default()));
+    chunks.map(Some).chain(std::iter::once(None)).map(
+        move |chunk| match chunk {
+            Some(chunk) => {
+                let e = enc.as_mut().unwrap();
+                e.write_all(&chunk).unwrap();
+                std::mem::take(e.get_mut())
+            }
+            None => enc.take().unwrap().finish().unwrap(),
+        },
+    )
+}
+
+fn protobuf(f: &Family) -> Vec<u8> {
+    let typ = match f.typ {
+        Type::Counter => PB_COUNTER,
+        Type::Gauge => PB_GAUGE,
+        Type::Histogram => PB_HISTOGRAM,
+    };
+
+    let metric = f
+        .samples
+        .iter()
+        .map(|s| {
+            let (v, histogram) = match &s.value {
+                Point::Scalar(v) => (Some(PbValue { value: v.as_f64() }), None),
+                Point::Histogram(h) => (
+                    None,
+                    Some(PbHistogram {
+                        sample_count: h.count(),
+                        sample_sum: h.sum(),
+                        bucket: h
+                            .buckets()
+                            .map(|(upper_bound, cumulative_count)| PbBucket {
+                                cumulative_count,
+                                upper_bound,
+                            })
+                            .collect(),
+                    }),
+                ),
+            };
+            PbMetric {
+                label: s
+                    .labels
+                    .iter()
+                    .map(|(k, v)| PbLabelPair {
+                        name: k.clone(),
+                        value: v.clone(),
+                    })
+                    .collect(),
+                gauge: if typ == PB_GAUGE { v.clone() } else { None },
+                counter: if typ == PB_COUNTER { v } else { None },
+                timestamp_ms: s.timestamp,
+                histogram,
+            }
+        })
+        .collect();
+
+    PbMetricFamily { name: f.name.clone(), help: f.desc.clone(), typ, metric }
+        .encode_length_delimited_to_vec()
+}
+
+/*
+ * The bucket lines of a histogram have an additional label, "le", with the
+ * upper bound of the bucket.
+ */
+fn labels(out: &mut impl Write, s: &Sample, le: Option<&str>) -> fmt::Result {
+    if s.labels.is_empty() && le.is_none() {
+        return Ok(());
+    }
+
+    out.write_char('{')?;
+    let le = le.map(|le| ("le", le));
+    let all = s.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())).chain(le);
+    for (i, (k, v)) in all.enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        write!(out, "{k}=\"{}\"", escape(v, true))?;
+    }
+    out.write_char('}')
+}
+
+/*
+ * Label values, and the text of HELP lines, may contain any character.  A
+ * backslash or newline must be escaped in either, and a double quote in a
+ * label value (or in any OpenMetrics HELP text) so that it cannot end the
+ * value early.
+ */
+fn escape(s: &str, quote: bool) -> std::borrow::Cow<'_, str> {
+    if !s.contains(|c| c == '\\' || c == '\n' || (quote && c == '"')) {
+        return s.into();
+    }
+
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '"' if quote => out += "\\\"",
+            c => out.push(c),
+        }
+    }
+    out.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(typ: Type, desc: &str, samples: Vec<Sample>) -> Family {
+        Family {
+            name: "temperature_degrees_celsius".into(),
+            typ,
+            desc: desc.into(),
+            samples,
+        }
+    }
+
+    fn sample(labels: &[(&str, &str)], value: Value) -> Sample {
+        Sample {
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            value: Point::Scalar(value),
+            timestamp: None,
+        }
+    }
+
+    fn render(x: Exposition, f: &Family) -> String {
+        let mut out = String::new();
+        x.family(f, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn value_special() {
+        assert_eq!(Value::F64(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::F64(-f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::F64(f64::INFINITY).to_string(), "+Inf");
+        assert_eq!(Value::F64(f64::NEG_INFINITY).to_string(), "-Inf");
+    }
+
+    #[test]
+    fn value_numbers() {
+        assert_eq!(Value::I64(-40).to_string(), "-40");
+        assert_eq!(Value::I64(i64::MIN).to_string(), "-9223372036854775808");
+        assert_eq!(Value::U64(u64::MAX).to_string(), "18446744073709551615");
+        assert_eq!(Value::F64(21.0).to_string(), "21");
+        assert_eq!(Value::F64(21.7).to_string(), "21.7");
+        assert_eq!(Value::F64(-0.5).to_string(), "-0.5");
+        assert_eq!(Value::F64(0.1 + 0.2).to_string(), "0.30000000000000004");
+    }
+
+    #[test]
+    fn escape_values() {
+        assert_eq!(escape("plain", true), "plain");
+        assert_eq!(escape(r"a\b", true), r"a\\b");
+        assert_eq!(escape("a\nb", true), r"a\nb");
+        assert_eq!(escape(r#"a"b"#, true), r#"a\"b"#);
+        assert_eq!(escape(r#"a"b"#, false), r#"a"b"#);
+        assert!(matches!(escape("plain", true), std::borrow::Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn text_values() {
+        let f = family(
+            Type::Gauge,
+            "temperature",
+            vec![
+                sample(&[("location", "a")], Value::F64(f64::NAN)),
+                sample(&[("location", "b")], Value::F64(f64::INFINITY)),
+                sample(&[("location", "c")], Value::F64(f64::NEG_INFINITY)),
+                sample(&[("location", "d")], Value::F64(20.0)),
+                sample(&[("location", "e")], Value::I64(3)),
+            ],
+        );
+
+        assert_eq!(
+            render(Exposition::Text, &f),
+            "# TYPE temperature_degrees_celsius gauge\n\
+            # HELP temperature_degrees_celsius temperature\n\
+            temperature_degrees_celsius{location=\"a\"}\tNaN\n\
+            temperature_degrees_celsius{location=\"b\"}\t+Inf\n\
+            temperature_degrees_celsius{location=\"c\"}\t-Inf\n\
+            temperature_degrees_celsius{location=\"d\"}\t20\n\
+            temperature_degrees_celsius{location=\"e\"}\t3\n",
+        );
+        assert_eq!(
+            render(Exposition::OpenMetrics, &f),
+            "# TYPE temperature_degrees_celsius gauge\n\
+            # UNIT temperature_degrees_celsius celsius\n\
+            # HELP temperature_degrees_celsius temperature\n\
+            temperature_degrees_celsius{location=\"a\"} NaN\n\
+            temperature_degrees_celsius{location=\"b\"} +Inf\n\
+            temperature_degrees_celsius{location=\"c\"} -Inf\n\
+            temperature_degrees_celsius{location=\"d\"} 20\n\
+            temperature_degrees_celsius{location=\"e\"} 3\n",
+        );
+    }
+
+    #[test]
+    fn text_escapes() {
+        let f = family(
+            Type::Gauge,
+            "a \"quoted\" back\\slash\nand newline",
+            vec![sample(
+                &[("location", "back\\slash \"quoted\"\nnewline")],
+                Value::F64(1.5),
+            )],
+        );
+
+        assert_eq!(
+            render(Exposition::Text, &f),
+            "# TYPE temperature_degrees_celsius gauge\n\
+            # HELP temperature_degrees_celsius a \"quoted\" back\\\\slash\\nand \
+            newline\n\
+            temperature_degrees_celsius{location=\"back\\\\slash \
+            \\\"quoted\\\"\\nnewline\"}\t1.5\n",
+        );
+        assert_eq!(
+            render(Exposition::OpenMetrics, &f),
+            "# TYPE temperature_degrees_celsius gauge\n\
+            # UNIT temperature_degrees_celsius celsius\n\
+            # HELP temperature_degrees_celsius a \\\"quoted\\\" \
+            back\\\\slash\\nand newline\n\
+            temperature_degrees_celsius{location=\"back\\\\slash \
+            \\\"quoted\\\"\\nnewline\"} 1.5\n",
+        );
+    }
+
+    #[test]
+    fn openmetrics_counter() {
+        let mut f =
+            family(Type::Counter, "messages", vec![sample(&[], Value::U64(7))]);
+        f.name = "temperature_sensor_messages_total".into();
+        f.samples[0].timestamp = Some(1_700_000_000_123);
+
+        assert_eq!(
+            render(Exposition::Text, &f),
+            "# TYPE temperature_sensor_messages_total counter\n\
+            # HELP temperature_sensor_messages_total messages\n\
+            temperature_sensor_messages_total\t7 1700000000123\n",
+        );
+        assert_eq!(
+            render(Exposition::OpenMetrics, &f),
+            "# TYPE temperature_sensor_messages counter\n\
+            # HELP temperature_sensor_messages messages\n\
+            temperature_sensor_messages_total 7 1700000000.123\n",
+        );
+    }
+
+    #[test]
+    fn openmetrics_trailer() {
+        let f = family(Type::Gauge, "t", vec![sample(&[], Value::F64(1.0))]);
+        let out = Exposition::OpenMetrics
+            .render(std::iter::once(f))
+            .flatten()
+            .collect::<Vec<_>>();
+        assert!(String::from_utf8(out).unwrap().ends_with("1\n# EOF\n"));
+    }
+}
@@ -0,0 +1,657 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/**
+ * The output format with which rtl_433 produced the records from an input
+ * source.
+ */
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /*
+     * Each record that begins with "{" or "[" is treated as JSON, a record
+     * that begins with a KV separator line is treated as KV, and anything
+     * else as CSV.
+     */
+    #[default]
+    Auto,
+    Json,
+    Csv,
+    Kv,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        Ok(match s {
+            "auto" => Format::Auto,
+            "json" => Format::Json,
+            "csv" => Format::Csv,
+            "kv" => Format::Kv,
+            other => bail!("unknown format {other:?}"),
+        })
+    }
+}
+
+/**
+ * Converts lines from an input source into the fields of a record.  Some
+ * formats carry state from one line to the next; e.g., CSV output begins with
+ * a header that names each column.
+ */
+pub struct Decoder {
+    format: Format,
+    /*
+     * Any incomplete JSON value from the lines we have read so far:
+     */
+    json: Vec<u8>,
+    columns: Option<Vec<String>>,
+    /*
+     * The fields of the KV record we are reading, if any:
+     */
+    kv: Option<Map<String, Value>>,
+}
+
+impl Decoder {
+    pub fn new(format: Format) -> Decoder {
+        Decoder { format, json: Vec::new(), columns: None, kv: None }
+    }
+
+    /**
+     * Decode a single line, returning the records that it completes.  A line
+     * may complete no records (e.g., a CSV header, or the first line of a
+     * pretty-printed JSON object), or several (e.g., a JSON array of events).
+     */
+    pub fn decode(&mut self, line: &[u8]) -> Result<Vec<Map<String, Value>>> {
+        let res = match self.format {
+            Format::Json => return self.json(line),
+            Format::Csv => self.csv(line),
+            Format::Kv => self.kv(line),
+            Format::Auto if !self.json.is_empty() || json_start(line) => {
+                return self.json(line);
+            }
+            Format::Auto if self.kv.is_some() || kv_separator(line) => {
+                self.kv(line)
+            }
+            Format::Auto => self.csv(line),
+        };
+        Ok(res?.into_iter().collect())
+    }
+
+    /*
+     * rtl_433 writes one JSON object per line, but records that have passed
+     * through other tools may have been pretty-printed across several lines,
+     * concatenated on one line, or collected into arrays of events.  We
+     * accumulate lines until we have at least one complete value, and keep
+     * any incomplete value at the end for the next line.
+     */
+    fn json(&mut self, line: &[u8]) -> Result<Vec<Map<String, Value>>> {
+        if self.json.is_empty() {
+            if let Ok(fields) = serde_json::from_slice(line) {
+                return Ok(vec![fields]);
+            }
+        }
+
+        let retry = !self.json.is_empty();
+        self.json.extend_from_slice(line);
+        self.json.push(b'\n');
+
+        let mut out = Vec::new();
+        let mut values =
+            serde_json::Deserializer::from_slice(&self.json).into_iter();
+        let res = loop {
+            match values.next() {
+                None => break Ok(()),
+                Some(Ok(v)) => {
+                    if let Err(e) = json_records(v, &mut out) {
+                        break Err(e);
+                    }
+                }
+                Some(Err(e)) if e.is_eof() => break Ok(()),
+                Some(Err(e)) => break Err(anyhow!(e)),
+            }
+        };
+        let used = values.byte_offset();
+
+        if let Err(e) = res {
+            self.json.clear();
+            if retry {
+                /*
+                 * The incomplete value from earlier lines may have been
+                 * truncated, e.g., if rtl_433 was interrupted while writing
+                 * it, in which case this line may yet be valid on its own.
+                 */
+                return self.json(line);
+            }
+            return Err(e);
+        }
+
+        self.json.drain(0..used);
+        if self.json.iter().all(u8::is_ascii_whitespace) {
+            self.json.clear();
+        } else if self.json.len() > JSON_MAX {
+            self.json.clear();
+            bail!("incomplete JSON value longer than {JSON_MAX} bytes");
+        }
+
+        Ok(out)
+    }
+
+    /**
+     * Return any record that has been read in full but not yet returned,
+     * because the format provides no way to tell that it was complete; e.g.,
+     * at the end of the input.
+     */
+    pub fn flush(&mut self) -> Option<Map<String, Value>> {
+        self.kv.as_mut().map(std::mem::take).filter(|fields| !fields.is_empty())
+    }
+
+    /*
+     * The default "-F kv" output from rtl_433 is intended for people rather
+     * than programs.  Each record begins with a separator line, followed by
+     * one or more lines of "Label: value" pairs padded into columns, and
+     * nothing marks the end of a record but the separator that begins the
+     * next one.  We return each record when we see that separator, or when
+     * flushed at the end of the input.
+     */
+    fn kv(&mut self, line: &[u8]) -> Result<Option<Map<String, Value>>> {
+        if kv_separator(line) {
+            let prev = self.kv.replace(Map::new());
+            return Ok(prev.filter(|fields| !fields.is_empty()));
+        }
+
+        let Some(fields) = &mut self.kv else {
+            bail!("KV fields before separator");
+        };
+        for (label, value) in kv_split(std::str::from_utf8(line)?) {
+            let (key, value) = kv_value(label, value);
+            fields.insert(key, value);
+        }
+
+        Ok(None)
+    }
+
+    /*
+     * With "-F csv", rtl_433 writes a header listing every field that any
+     * enabled decoder may produce, and then one line per record with a value
+     * in each column for which the device provided one.  The header is
+     * written again each time rtl_433 starts, so we recognise it wherever it
+     * appears.
+     */
+    fn csv(&mut self, line: &[u8]) -> Result<Option<Map<String, Value>>> {
+        let values = csv_split(std::str::from_utf8(line)?);
+
+        if values.iter().any(|v| v == "time")
+            && values.iter().any(|v| v == "model")
+        {
+            self.columns = Some(values);
+            return Ok(None);
+        }
+
+        let Some(columns) = &self.columns else {
+            bail!("CSV record before header");
+        };
+        if values.len() > columns.len() {
+            bail!(
+                "CSV record has {} columns, but header has {}",
+                values.len(),
+                columns.len(),
+            );
+        }
+
+        Ok(Some(
+            columns
+                .iter()
+                .zip(values)
+                .filter(|(_, v)| !v.is_empty())
+                .map(|(c, v)| (c.clone(), csv_value(c, v)))
+                .collect(),
+        ))
+    }
+}
+
+const JSON_MAX: usize = 1024 * 1024;
+
+fn json_start(line: &[u8]) -> bool {
+    matches!(line.trim_ascii_start().first(), Some(b'{' | b'['))
+}
+
+fn json_records(v: Value, out: &mut Vec<Map<String, Value>>) -> Result<()> {
+    match v {
+        Value::Object(fields) => out.push(fields),
+        Value::Array(a) => {
+            for v in a {
+                let Value::Object(fields) = v else {
+                    bail!("JSON array contains a value that is not an object");
+                };
+                out.push(fields);
+            }
+        }
+        _ => bail!("JSON value is not an object"),
+    }
+    Ok(())
+}
+
+fn kv_separator(line: &[u8]) -> bool {
+    line.starts_with(b"_ _ _")
+}
+
+/*
+ * Split a line of KV output into "Label: value" pairs.  Values may contain
+ * single spaces (e.g., "21.5 C"), as may labels (e.g., "Battery Ok"), but each
+ * pair is padded from the next by at least two spaces.  Colons not followed by
+ * a space, as in the time, are part of the value.
+ */
+fn kv_split(line: &str) -> Vec<(&str, &str)> {
+    let mut out = Vec::new();
+    let mut parts = line.split(": ");
+    let Some(mut label) = parts.next() else {
+        return out;
+    };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        let (value, next) = if parts.peek().is_some() {
+            match part.trim_end().rsplit_once("  ") {
+                Some((value, next)) => (value, next),
+                None => (part, ""),
+            }
+        } else {
+            (part, "")
+        };
+
+        out.push((label.trim(), value.trim()));
+        label = next;
+    }
+
+    out
+}
+
+/*
+ * Labels in the KV output are the descriptions provided by each decoder,
+ * rather than the field names in the JSON output.  Most are the field name
+ * with different capitalisation (e.g., "Humidity"), but for some the name is
+ * quite different:
+ */
+const KV_LABELS: &[(&str, &str)] = &[
+    ("integrity", "mic"),
+    ("battery", "battery_ok"),
+    ("wind speed", "wind_avg"),
+    ("wind avg speed", "wind_avg"),
+    ("wind gust", "wind_max"),
+    ("gust speed", "wind_max"),
+    ("wind max speed", "wind_max"),
+    ("wind direction", "wind_dir_deg"),
+    ("total rain", "rain"),
+    ("rain total", "rain"),
+];
+
+/*
+ * Numeric values are followed by their unit, which rtl_433 places in the field
+ * name in the JSON output (e.g., "temperature_C").  Units that are not part of
+ * the field name (e.g., "%" for humidity) are discarded.
+ */
+const KV_UNITS: &[(&str, &str)] = &[
+    ("C", "_C"),
+    ("F", "_F"),
+    ("Pa", "_Pa"),
+    ("hPa", "_hPa"),
+    ("kPa", "_kPa"),
+    ("PSI", "_PSI"),
+    ("inHg", "_inHg"),
+    ("km/h", "_km_h"),
+    ("m/s", "_m_s"),
+    ("mi/h", "_mi_h"),
+    ("mph", "_mph"),
+    ("kn", "_kn"),
+    ("mm", "_mm"),
+    ("mm/h", "_mm_h"),
+    ("in", "_in"),
+    ("in/h", "_in_h"),
+    ("V", "_V"),
+    ("mV", "_mV"),
+];
+
+fn kv_value(label: &str, value: &str) -> (String, Value) {
+    let label = label.to_lowercase();
+    let mut key = KV_LABELS
+        .iter()
+        .find(|(l, _)| *l == label)
+        .map(|(_, k)| k.to_string())
+        .unwrap_or_else(|| label.replace(' ', "_"));
+
+    if key == "time" {
+        return (key, Value::String(value.to_string()));
+    }
+
+    let (number, unit) = value.split_once(' ').unwrap_or((value, ""));
+    let number = if let Ok(n) = number.parse::<i64>() {
+        Value::from(n)
+    } else if let Some(n) =
+        number.parse::<f64>().ok().and_then(serde_json::Number::from_f64)
+    {
+        Value::Number(n)
+    } else {
+        return (key, Value::String(value.to_string()));
+    };
+
+    if let Some((_, suffix)) = KV_UNITS.iter().find(|(u, _)| *u == unit) {
+        key += suffix;
+    }
+    (key, number)
+}
+
+/*
+ * Columns are separated by commas.  rtl_433 does not quote values, but
+ * escapes any comma within a value with a backslash.  A file that has been
+ * through another program, such as a spreadsheet, may instead have quoted
+ * values, within which a doubled quote stands for a single quote.
+ */
+fn csv_split(line: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cur.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cur.is_empty() => quoted = true,
+            '\\' if !quoted => cur.extend(chars.next()),
+            ',' if !quoted => out.push(std::mem::take(&mut cur)),
+            c => cur.push(c),
+        }
+    }
+    out.push(cur);
+    out
+}
+
+/*
+ * CSV does not distinguish numbers from strings, so any value that looks like
+ * a number is treated as one, as it would have been in the JSON output.  The
+ * time is always a string, even when rtl_433 is configured to report it in
+ * seconds since the epoch.
+ */
+fn csv_value(column: &str, v: String) -> Value {
+    if column != "time" {
+        if let Ok(n) = v.parse::<i64>() {
+            return Value::from(n);
+        }
+        if let Some(n) =
+            v.parse::<f64>().ok().and_then(serde_json::Number::from_f64)
+        {
+            return Value::Number(n);
+        }
+    }
+    Value::String(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /*
+     * Decode each line in turn, and then flush the decoder, collecting every
+     * record that it returns.
+     */
+    fn decode(format: Format, lines: &[&str]) -> Result<Value> {
+        let mut d = Decoder::new(format);
+        let mut out = Vec::new();
+        for l in lines {
+            out.extend(d.decode(l.as_bytes())?.into_iter().map(Value::Object));
+        }
+        out.extend(d.flush().map(Value::Object));
+        Ok(Value::Array(out))
+    }
+
+    #[test]
+    fn json() {
+        let cases: Vec<(&[&str], Value)> = vec![
+            (
+                &[
+                    r#"{"time" : "2024-03-01 12:34:56", "model" : "Acurite-Tower", "id" : 5019, "channel" : "C", "battery_ok" : 1, "temperature_C" : 21.700, "humidity" : 45, "mic" : "CHECKSUM"}"#,
+                ],
+                json!([{
+                    "time": "2024-03-01 12:34:56",
+                    "model": "Acurite-Tower",
+                    "id": 5019,
+                    "channel": "C",
+                    "battery_ok": 1,
+                    "temperature_C": 21.7,
+                    "humidity": 45,
+                    "mic": "CHECKSUM",
+                }]),
+            ),
+            /*
+             * Pretty-printed across several lines:
+             */
+            (
+                &[
+                    "{",
+                    r#"  "time" : "2024-03-01 12:35:10","#,
+                    r#"  "model" : "LaCrosse-TX141THBv2","#,
+                    r#"  "id" : 113,"#,
+                    r#"  "temperature_C" : 18.200"#,
+                    "}",
+                ],
+                json!([{
+                    "time": "2024-03-01 12:35:10",
+                    "model": "LaCrosse-TX141THBv2",
+                    "id": 113,
+                    "temperature_C": 18.2,
+                }]),
+            ),
+            /*
+             * Concatenated on one line, and collected into an array:
+             */
+            (
+                &[
+                    r#"{"model" : "Nexus-TH", "id" : 1}{"model" : "Nexus-TH", "id" : 2}"#,
+                    r#"[{"model" : "Nexus-TH", "id" : 3}, {"model" : "Nexus-TH", "id" : 4}]"#,
+                ],
+                json!([
+                    {"model": "Nexus-TH", "id": 1},
+                    {"model": "Nexus-TH", "id": 2},
+                    {"model": "Nexus-TH", "id": 3},
+                    {"model": "Nexus-TH", "id": 4},
+                ]),
+            ),
+            /*
+             * A value truncated when rtl_433 was interrupted, followed by a
+             * complete one:
+             */
+            (
+                &[
+                    r#"{"time" : "2024-03-01 12:36:00", "model" : "Acurite-606TX", "#,
+                    r#"{"time" : "2024-03-01 12:36:30", "model" : "Acurite-606TX", "id" : 48, "temperature_C" : -3.100}"#,
+                ],
+                json!([{
+                    "time": "2024-03-01 12:36:30",
+                    "model": "Acurite-606TX",
+                    "id": 48,
+                    "temperature_C": -3.1,
+                }]),
+            ),
+        ];
+
+        for (lines, expected) in cases {
+            assert_eq!(decode(Format::Json, lines).unwrap(), expected);
+            assert_eq!(decode(Format::Auto, lines).unwrap(), expected);
+        }
+
+        assert!(decode(Format::Json, &["[1, 2]"]).is_err());
+        assert!(decode(Format::Json, &[r#""model""#]).is_err());
+    }
+
+    const CSV_HEADER: &str = "time,msg,codes,model,count,num_rows,rows,len,data,id,channel,battery_ok,temperature_C,humidity,mic";
+
+    #[test]
+    fn csv() {
+        let cases: Vec<(&[&str], Value)> = vec![
+            /*
+             * A header alone, as written when rtl_433 starts:
+             */
+            (&[CSV_HEADER], json!([])),
+            (
+                &[
+                    CSV_HEADER,
+                    "2024-03-01 12:34:56,,,Acurite-Tower,,,,,,5019,C,1,21.7,45,CHECKSUM",
+                    "2024-03-01 12:35:10,,,LaCrosse-TX141THBv2,,,,,,113,0,1,18.2,,CRC",
+                ],
+                json!([
+                    {
+                        "time": "2024-03-01 12:34:56",
+                        "model": "Acurite-Tower",
+                        "id": 5019,
+                        "channel": "C",
+                        "battery_ok": 1,
+                        "temperature_C": 21.7,
+                        "humidity": 45,
+                        "mic": "CHECKSUM",
+                    },
+                    {
+                        "time": "2024-03-01 12:35:10",
+                        "model": "LaCrosse-TX141THBv2",
+                        "id": 113,
+                        "channel": 0,
+                        "battery_ok": 1,
+                        "temperature_C": 18.2,
+                        "mic": "CRC",
+                    },
+                ]),
+            ),
+            /*
+             * The time is a string even in seconds since the epoch, and the
+             * header is recognised again when rtl_433 restarts with other
+             * decoders enabled:
+             */
+            (
+                &[
+                    "time,model,id,temperature_C",
+                    "1709296496,Nexus-TH,1,20.5",
+                    "time,model,id,wind_avg_km_h",
+                    "1709296500,Fineoffset-WH24,2,3.4",
+                ],
+                json!([
+                    {
+                        "time": "1709296496",
+                        "model": "Nexus-TH",
+                        "id": 1,
+                        "temperature_C": 20.5,
+                    },
+                    {
+                        "time": "1709296500",
+                        "model": "Fineoffset-WH24",
+                        "id": 2,
+                        "wind_avg_km_h": 3.4,
+                    },
+                ]),
+            ),
+            /*
+             * Commas escaped by rtl_433, and values quoted by another program:
+             */
+            (
+                &[
+                    r#""time","model","id","codes""#,
+                    r#"2024-03-01 12:34:56,Generic-Remote,7,{25}1a2b\,{25}3c4d"#,
+                    r#""2024-03-01 12:35:00","Generic-Remote","8","{25}1a2b,""x""""#,
+                ],
+                json!([
+                    {
+                        "time": "2024-03-01 12:34:56",
+                        "model": "Generic-Remote",
+                        "id": 7,
+                        "codes": "{25}1a2b,{25}3c4d",
+                    },
+                    {
+                        "time": "2024-03-01 12:35:00",
+                        "model": "Generic-Remote",
+                        "id": 8,
+                        "codes": r#"{25}1a2b,"x""#,
+                    },
+                ]),
+            ),
+        ];
+
+        for (lines, expected) in cases {
+            assert_eq!(decode(Format::Csv, lines).unwrap(), expected);
+            assert_eq!(decode(Format::Auto, lines).unwrap(), expected);
+        }
+
+        assert!(
+            decode(Format::Csv, &["2024-03-01 12:34:56,Nexus-TH,1"]).is_err()
+        );
+        assert!(decode(Format::Csv, &["time,model", "2024-03-01,Nexus-TH,1"])
+            .is_err());
+    }
+
+    const KV_SEPARATOR: &str = "_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _";
+
+    #[test]
+    fn kv() {
+        let cases: Vec<(&[&str], Value)> = vec![
+            (&[KV_SEPARATOR], json!([])),
+            /*
+             * Each record continues over several lines, and is held until the
+             * next separator, or the end of the input:
+             */
+            (
+                &[
+                    KV_SEPARATOR,
+                    "time      : 2024-03-01 12:34:56",
+                    "model     : Acurite-Tower  id        : 5019",
+                    "channel   : C             Battery Ok: 1             Temperature: 21.7 C       Humidity  : 45 %",
+                    "Integrity : CHECKSUM",
+                    KV_SEPARATOR,
+                    "time      : 2024-03-01 12:35:02",
+                    "model     : Fineoffset-WH24                          ID        : 146",
+                    "Battery   : 1             Temperature: 70.3 F       Wind Speed: 5.2 mi/h      Gust speed: 7.0 mi/h",
+                    "Wind direction: 270       Total rain: 0.12 in",
+                ],
+                json!([
+                    {
+                        "time": "2024-03-01 12:34:56",
+                        "model": "Acurite-Tower",
+                        "id": 5019,
+                        "channel": "C",
+                        "battery_ok": 1,
+                        "temperature_C": 21.7,
+                        "humidity": 45,
+                        "mic": "CHECKSUM",
+                    },
+                    {
+                        "time": "2024-03-01 12:35:02",
+                        "model": "Fineoffset-WH24",
+                        "id": 146,
+                        "battery_ok": 1,
+                        "temperature_F": 70.3,
+                        "wind_avg_mi_h": 5.2,
+                        "wind_max_mi_h": 7.0,
+                        "wind_dir_deg": 270,
+                        "rain_in": 0.12,
+                    },
+                ]),
+            ),
+        ];
+
+        for (lines, expected) in cases {
+            assert_eq!(decode(Format::Kv, lines).unwrap(), expected);
+            assert_eq!(decode(Format::Auto, lines).unwrap(), expected);
+        }
+
+        assert!(decode(Format::Kv, &["model     : Acurite-Tower"]).is_err());
+    }
+}
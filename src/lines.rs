@@ -0,0 +1,295 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::io::BufRead;
+
+/**
+ * A line found in the input by a LineSplitter.
+ */
+pub enum Line<'a> {
+    /**
+     * A complete line, without the trailing newline.
+     */
+    Complete(&'a [u8]),
+    /**
+     * A line that was longer than the maximum length, and has been discarded.
+     */
+    TooLong,
+}
+
+/**
+ * Splits a stream of bytes, which may arrive in chunks of any size, into
+ * lines.  A line that is split across chunks is accumulated until it is
+ * complete.  A line that grows beyond the maximum length is discarded, along
+ * with the rest of it as it arrives, so that a writer that never produces a
+ * newline cannot cause us to buffer without bound.
+ */
+pub struct LineSplitter {
+    max: usize,
+    line: Vec<u8>,
+    skip: Skip,
+}
+
+#[derive(PartialEq)]
+enum Skip {
+    None,
+    Partial,
+    TooLong,
+}
+
+impl LineSplitter {
+    pub fn new(max: usize) -> LineSplitter {
+        LineSplitter { max, line: Vec::new(), skip: Skip::None }
+    }
+
+    /**
+     * Quietly discard everything up to and including the next newline; e.g.,
+     * after seeking to an arbitrary position in a file.
+     */
+    pub fn skip_partial(&mut self) {
+        self.line.clear();
+        self.skip = Skip::Partial;
+    }
+
+    /**
+     * The number of bytes of an incomplete line held from earlier chunks, or
+     * None if we are discarding the rest of a line and thus do not know where
+     * the current line began.
+     */
+    pub fn pending(&self) -> Option<usize> {
+        match self.skip {
+            Skip::None => Some(self.line.len()),
+            Skip::Partial | Skip::TooLong => None,
+        }
+    }
+
+    /**
+     * Process the next chunk of input, passing each line that it completes to
+     * the callback.
+     */
+    pub fn split(&mut self, mut data: &[u8], mut f: impl FnMut(Line)) {
+        loop {
+            let nl = memchr::memchr(b'\n', data);
+            let part = &data[0..nl.unwrap_or(data.len())];
+
+            if self.skip == Skip::None
+                && self.line.len() + part.len() > self.max
+            {
+                self.line.clear();
+                self.skip = Skip::TooLong;
+            }
+
+            let Some(n) = nl else {
+                if self.skip == Skip::None {
+                    self.line.extend_from_slice(part);
+                }
+                return;
+            };
+
+            match self.skip {
+                Skip::None if self.line.is_empty() => {
+                    /*
+                     * If there is nothing in the accumulator, we can pass on
+                     * the line directly from the input without copying it.
+                     */
+                    f(Line::Complete(part));
+                }
+                Skip::None => {
+                    self.line.extend_from_slice(part);
+                    f(Line::Complete(&self.line));
+                    self.line.clear();
+                }
+                Skip::Partial => (),
+                Skip::TooLong => f(Line::TooLong),
+            }
+
+            self.skip = Skip::None;
+            data = &data[n + 1..];
+        }
+    }
+
+    /**
+     * At the end of the input, pass on any final line that was not terminated
+     * by a newline.
+     */
+    pub fn finish(&mut self, mut f: impl FnMut(Line)) {
+        match self.skip {
+            Skip::None if self.line.is_empty() => (),
+            Skip::None => f(Line::Complete(&self.line)),
+            Skip::Partial => (),
+            Skip::TooLong => f(Line::TooLong),
+        }
+
+        self.line.clear();
+        self.skip = Skip::None;
+    }
+}
+
+/**
+ * Read lines from a buffered reader until the end of the input.
+ */
+pub fn read_lines(
+    mut r: impl BufRead,
+    max: usize,
+    mut f: impl FnMut(Line),
+) -> std::io::Result<()> {
+    let mut ls = LineSplitter::new(max);
+    loop {
+        let buf = r.fill_buf()?;
+        if buf.is_empty() {
+            ls.finish(&mut f);
+            return Ok(());
+        }
+
+        let sz = buf.len();
+        ls.split(buf, &mut f);
+        r.consume(sz);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * Each line found, or None for one that was too long.
+     */
+    fn collect(out: &mut Vec<Option<String>>) -> impl FnMut(Line) + '_ {
+        |l| {
+            out.push(match l {
+                Line::Complete(l) => {
+                    Some(String::from_utf8(l.to_vec()).unwrap())
+                }
+                Line::TooLong => None,
+            })
+        }
+    }
+
+    fn split(ls: &mut LineSplitter, chunks: &[&str]) -> Vec<Option<String>> {
+        let mut out = Vec::new();
+        for c in chunks {
+            ls.split(c.as_bytes(), collect(&mut out));
+        }
+        ls.finish(collect(&mut out));
+        out
+    }
+
+    fn lines(v: &[&str]) -> Vec<Option<String>> {
+        v.iter().map(|l| Some(l.to_string())).collect()
+    }
+
+    #[test]
+    fn split_across_chunks() {
+        let mut ls = LineSplitter::new(100);
+        assert_eq!(
+            split(&mut ls, &["{\"a\":", "1}\n{\"b\"", ":2}\n\n", "{}\n"]),
+            lines(&["{\"a\":1}", "{\"b\":2}", "", "{}"]),
+        );
+
+        let mut out = Vec::new();
+        ls.split(b"abc", collect(&mut out));
+        assert_eq!(ls.pending(), Some(3));
+        ls.split(b"de", collect(&mut out));
+        assert_eq!(ls.pending(), Some(5));
+        ls.split(b"f\n", collect(&mut out));
+        assert_eq!(ls.pending(), Some(0));
+        assert_eq!(out, lines(&["abcdef"]));
+    }
+
+    #[test]
+    fn crlf() {
+        /*
+         * Lines end only at a newline.  A carriage return before it is left
+         * for the decoder, which strips it and counts it.
+         */
+        let mut ls = LineSplitter::new(100);
+        assert_eq!(
+            split(&mut ls, &["a\r\n", "b\r", "\nc\rd\n"]),
+            lines(&["a\r", "b\r", "c\rd"]),
+        );
+    }
+
+    #[test]
+    fn too_long() {
+        let mut ls = LineSplitter::new(4);
+        let mut out = Vec::new();
+        ls.split(b"abcd\nabcde\nab", collect(&mut out));
+        ls.split(b"cdef", collect(&mut out));
+        assert_eq!(ls.pending(), None);
+        ls.split(b"gh", collect(&mut out));
+        ls.split(b"ij\nxy\n", collect(&mut out));
+        assert_eq!(ls.pending(), Some(0));
+        assert_eq!(
+            out,
+            vec![Some("abcd".into()), None, None, Some("xy".into())],
+        );
+
+        /*
+         * A line that is too long is reported even if the input ends before
+         * its newline.
+         */
+        assert_eq!(
+            split(&mut ls, &["ab\n", "abcdefgh"]),
+            vec![Some("ab".into()), None],
+        );
+    }
+
+    #[test]
+    fn skip_partial() {
+        let mut ls = LineSplitter::new(100);
+        ls.skip_partial();
+        assert_eq!(ls.pending(), None);
+        assert_eq!(
+            split(&mut ls, &["tial\nwhole\npart", "ial"]),
+            lines(&["whole", "partial"]),
+        );
+
+        /*
+         * Nothing is emitted for the skipped part, even if it is too long,
+         * or ends the input.
+         */
+        let mut ls = LineSplitter::new(4);
+        ls.skip_partial();
+        assert_eq!(split(&mut ls, &["abcdefgh", "ij\nab\n"]), lines(&["ab"]));
+        ls.skip_partial();
+        assert_eq!(split(&mut ls, &["abc"]), lines(&[]));
+    }
+
+    #[test]
+    fn finish() {
+        let mut ls = LineSplitter::new(100);
+        let mut out = Vec::new();
+        ls.split(b"a\nb", collect(&mut out));
+        assert_eq!(out, lines(&["a"]));
+        assert_eq!(ls.pending(), Some(1));
+        ls.finish(collect(&mut out));
+        assert_eq!(out, lines(&["a", "b"]));
+        assert_eq!(ls.pending(), Some(0));
+
+        /*
+         * The splitter is ready for more input afterwards, and there is no
+         * line to emit if the input ended with a newline.
+         */
+        assert_eq!(split(&mut ls, &["c\n"]), lines(&["c"]));
+    }
+
+    #[test]
+    fn buffered_reader() {
+        let r = std::io::BufReader::with_capacity(
+            3,
+            "one\ntwo\r\nthreefold\nfour".as_bytes(),
+        );
+        let mut out = Vec::new();
+        read_lines(r, 5, collect(&mut out)).unwrap();
+        assert_eq!(
+            out,
+            vec![
+                Some("one".into()),
+                Some("two\r".into()),
+                None,
+                Some("four".into()),
+            ],
+        );
+    }
+}
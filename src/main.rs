@@ -4,32 +4,72 @@
 
 use anyhow::{anyhow, bail, Result};
 use dropshot::{
-    endpoint, ApiDescription, ConfigDropshot, ConfigLogging,
-    ConfigLoggingLevel, HttpError, HttpServerStarter, RequestContext,
+    ApiDescription, ConfigDropshot, ConfigLogging, ConfigLoggingIfExists,
+    ConfigLoggingLevel, HttpError, HttpServerStarter,
 };
 use getopts::{Matches, Options};
-use hyper::{Body, Response};
-use slog::{crit, info, o, warn, Logger};
-use std::collections::{HashMap, HashSet};
+use hyper::{body::Bytes, Body};
+use slog::{crit, error, info, o, warn, Logger};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::result::Result as StdResult;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::OnceCell;
 
+/*
+ * The dropshot 0.10 endpoint macro emits type-checking structs that are never
+ * constructed, which current compilers report as dead code.  Each module
+ * defines its endpoints within endpoints!{}, which allows the lint for those
+ * definitions alone, and makes them available from the module as usual.
+ */
+macro_rules! endpoints {
+    ($($item:item)*) => {
+        #[allow(dead_code)]
+        mod defs {
+            use super::*;
+
+            $($item)*
+        }
+        pub use defs::*;
+    };
+}
+
+mod alarm;
+mod api;
+mod auth;
+mod checkpoint;
+mod child;
+mod config;
+mod cors;
+mod daemon;
+mod dashboard;
+mod derived;
+mod endpoints;
+mod exposition;
+mod format;
+mod lines;
+mod mqtt;
+mod privilege;
+mod process;
+mod ratelimit;
 mod sdr;
+mod smf;
+mod stream;
+mod syslog;
+mod systemd;
+mod tls;
+mod unix;
+mod watch;
 
 trait AnyhowHttpError<T> {
-    fn or_500(self) -> StdResult<T, HttpError>;
     fn or_400(self) -> StdResult<T, HttpError>;
 }
 
 impl<T> AnyhowHttpError<T> for Result<T> {
-    fn or_500(self) -> StdResult<T, HttpError> {
-        self.map_err(|e| {
-            let msg = format!("internal error: {}", e);
-            HttpError::for_internal_error(msg)
-        })
-    }
-
     fn or_400(self) -> StdResult<T, HttpError> {
         self.map_err(|e| {
             HttpError::for_client_error(
@@ -42,202 +82,1364 @@ impl<T> AnyhowHttpError<T> for Result<T> {
 }
 
 struct Main {
-    sdr: sdr::SdrTail,
+    sensors: sdr::Sensors,
+    tails: watch::Tails,
+    /*
+     * Network input sources, which are configured at startup:
+     */
+    inputs: Vec<sdr::Source>,
+    config_path: Option<PathBuf>,
+    config: Mutex<Arc<config::Config>>,
+    silences: Mutex<alarm::Silences>,
+    start_time: f64,
+    started: Instant,
+    scrapes: Mutex<Scrapes>,
+    cache: Mutex<HashMap<CacheKey, CacheEntry>>,
+    openapi: Bytes,
+    limiter: ratelimit::Limiter,
+}
+
+/*
+ * Rendered responses are cached by format, compression, and the metric
+ * families requested:
+ */
+type CacheKey = (exposition::Exposition, bool, BTreeSet<String>);
+
+/*
+ * Each entry records when it was created, and holds the body once it has been
+ * rendered:
+ */
+type CacheEntry = (Instant, Arc<OnceCell<Bytes>>);
+
+/*
+ * A scrape cannot report its own duration, so each scrape reports the
+ * duration of the one before it.
+ */
+#[derive(Default)]
+struct Scrapes {
+    count: u64,
+    last_duration: Option<Duration>,
+}
+
+impl Main {
+    fn config(&self) -> Arc<config::Config> {
+        Arc::clone(&self.config.lock().unwrap())
+    }
+
+    /**
+     * Every input source from which we are currently receiving records.
+     */
+    fn sources(&self) -> Vec<sdr::Source> {
+        self.tails
+            .list()
+            .iter()
+            .map(|t| t.source().clone())
+            .chain(self.inputs.iter().cloned())
+            .collect()
+    }
+
+    /**
+     * Determine whether no input source has produced a record for the given
+     * period.  Until the first record arrives, the period is measured from
+     * when we started.
+     */
+    fn silent(&self, period: Duration) -> bool {
+        let last = self
+            .sources()
+            .iter()
+            .filter_map(|s| s.stats().last_record)
+            .max()
+            .unwrap_or(self.started);
+        last.elapsed() > period
+    }
+
+    /**
+     * Re-read the configuration file, returning a description of what
+     * changed.  If the new configuration is not valid, the existing
+     * configuration remains in effect.
+     */
+    fn reload(&self) -> Result<Vec<String>> {
+        let Some(path) = &self.config_path else {
+            bail!("no configuration file was specified");
+        };
+
+        let c = Arc::new(config::Config::load(path)?);
+        self.sensors.set_config(Arc::clone(&c));
+
+        let mut config = self.config.lock().unwrap();
+        let changes = config.diff(&c);
+        *config = c;
+
+        Ok(changes)
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let mut opts = Options::new();
 
-    opts.optopt("b", "", "bind address:port", "ADDRESS:PORT");
+    opts.optmulti(
+        "b",
+        "",
+        "bind address:port, or unix:PATH for a Unix domain socket (may be \
+        repeated)",
+        "ADDRESS:PORT",
+    );
+    opts.optopt(
+        "",
+        "admin-bind",
+        "serve administrative endpoints only on this loopback address:port",
+        "ADDRESS:PORT",
+    );
+    opts.optopt(
+        "",
+        "socket-mode",
+        "permissions for a Unix domain socket (e.g., 660)",
+        "MODE",
+    );
+    opts.optopt("c", "", "configuration file", "CONFIG");
+    opts.optopt(
+        "f",
+        "format",
+        "data file format (auto, json, csv, kv)",
+        "FORMAT",
+    );
+    opts.optopt(
+        "",
+        "tls-cert",
+        "serve HTTPS with this PEM certificate chain",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "tls-key",
+        "private key (PEM, PKCS #8) for the certificate",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "tls-client-ca",
+        "require client certificates signed by a CA in this PEM file",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "user",
+        "switch to this user once listening (and to its group, unless \
+        --group is given)",
+        "USER",
+    );
+    opts.optopt("", "group", "switch to this group once listening", "GROUP");
+    opts.optflag(
+        "d",
+        "daemon",
+        "detach from the terminal and run in the background",
+    );
+    opts.optopt("", "pidfile", "write the process ID to this file", "FILE");
+    opts.optopt("", "log-file", "log in bunyan format to this file", "FILE");
+    opts.optflag("", "openapi", "print the OpenAPI description and exit");
+    opts.optflag(
+        "",
+        "smf",
+        "run as an SMF service: log in bunyan format and use SMF exit codes",
+    );
+    opts.optflag(
+        "",
+        "smf-manifest",
+        "print an SMF manifest that runs with the other arguments and exit",
+    );
 
     let p = match opts.parse(std::env::args().skip(1)) {
         Ok(p) => p,
         Err(e) => {
             eprintln!("ERROR: usage: {}", e);
             eprintln!("       {}", opts.usage("usage"));
-            std::process::exit(1);
+            let smf = std::env::args().any(|a| a == "--smf");
+            std::process::exit(if smf { smf::EXIT_ERR_CONFIG } else { 1 });
         }
     };
 
-    if p.free.len() != 1 {
-        bail!("specify data file name");
+    if p.opt_present("openapi") {
+        let api = api_description(Endpoints::All);
+        std::io::stdout().write_all(&openapi_json(&api)?)?;
+        return Ok(());
     }
-    let file = PathBuf::from(&p.free[0]);
 
-    let cfglog =
-        ConfigLogging::StderrTerminal { level: ConfigLoggingLevel::Info };
+    if p.opt_present("smf-manifest") {
+        /*
+         * The service runs us with the same arguments we were given here,
+         * but in SMF mode.
+         */
+        let mut args = vec![std::env::current_exe()?.display().to_string()];
+        args.push("--smf".into());
+        args.extend(
+            std::env::args()
+                .skip(1)
+                .filter(|a| a != "--smf-manifest" && a != "--smf"),
+        );
+        print!("{}", smf::manifest(&args));
+        return Ok(());
+    }
+
+    /*
+     * Under SMF, standard error is the service log file, which is better
+     * served by one JSON record per line than by terminal formatting.  A
+     * daemon has no standard error, so must be given a log file.
+     */
+    let smf = p.opt_present("smf");
+    let log_file = match p.opt_str("log-file") {
+        Some(path) => Some(path),
+        None if smf => Some("/dev/stderr".to_string()),
+        None if p.opt_present("d") => {
+            eprintln!("ERROR: usage: --daemon requires --log-file");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let cfglog = if let Some(path) = log_file {
+        ConfigLogging::File {
+            level: ConfigLoggingLevel::Info,
+            path: path.into(),
+            if_exists: ConfigLoggingIfExists::Append,
+        }
+    } else {
+        ConfigLogging::StderrTerminal { level: ConfigLoggingLevel::Info }
+    };
+
+    /*
+     * We must detach before any thread is started, including the one that
+     * writes the log.
+     */
+    if p.opt_present("d") {
+        daemon::detach()?;
+    }
     let log = cfglog.to_logger("temperature-exporter")?;
 
-    if let Err(e) = run(log.clone(), p, file).await {
+    let res = tokio::runtime::Runtime::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|rt| rt.block_on(run(log.clone(), p)));
+    if let Err(e) = res {
         crit!(log, "critical failure: {:?}", e);
-        std::process::exit(1);
+        let code = if !smf {
+            1
+        } else if e.is::<smf::ConfigError>() {
+            smf::EXIT_ERR_CONFIG
+        } else {
+            smf::EXIT_ERR_FATAL
+        };
+        std::process::exit(code);
     }
 
     Ok(())
 }
 
-struct EmitterStat {
-    name: String,
-    typ: String,
-    desc: String,
-    label_name: String,
+/*
+ * Samples are collected for each metric family as they are emitted, so that
+ * all of the samples for a family appear together in the output regardless
+ * of the order in which they were emitted.
+ */
+struct Emitter {
+    typedefs: HashMap<String, exposition::Family>,
+    order: Vec<String>,
+    fahrenheit: Option<config::Fahrenheit>,
+    prefix: String,
+    labels: Vec<(String, String)>,
+    /*
+     * If the client asked for particular metric families, samples for any
+     * other family are discarded as they are emitted:
+     */
+    only: Option<BTreeSet<String>>,
 }
 
-struct Emitter {
-    typedefs: HashMap<String, EmitterStat>,
-    printed: HashSet<String>,
-    out: String,
+/*
+ * If requested, each metric in degrees celsius has a counterpart in degrees
+ * fahrenheit with the same name but for the unit.
+ */
+fn fahrenheit_name(stat_name: &str) -> Option<String> {
+    stat_name.strip_suffix("_celsius").map(|base| format!("{base}_fahrenheit"))
 }
 
 impl Emitter {
-    fn new() -> Emitter {
+    fn new(c: &config::Config) -> Emitter {
         Emitter {
             typedefs: HashMap::new(),
-            printed: HashSet::new(),
-            out: String::new(),
+            order: Vec::new(),
+            fahrenheit: c.fahrenheit,
+            prefix: c.prefix.clone(),
+            labels: c
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            only: None,
         }
     }
 
+    /**
+     * Emit only the nominated metric families.  The names are those that
+     * appear in the output; i.e., including any prefix.
+     */
+    fn only(&mut self, names: BTreeSet<String>) {
+        self.only = Some(names);
+    }
+
+    fn define_gauge(&mut self, stat_name: &str, stat_desc: &str) {
+        self.define(stat_name, exposition::Type::Gauge, stat_desc);
+    }
+
+    /**
+     * Counters are distinguished by the "_total" suffix on their name, which
+     * we insist on here so that a counter cannot be defined without it.
+     */
+    fn define_counter(&mut self, stat_name: &str, stat_desc: &str) {
+        assert!(
+            stat_name.ends_with("_total"),
+            "counter {stat_name:?} must end in \"_total\"",
+        );
+        self.define(stat_name, exposition::Type::Counter, stat_desc);
+    }
+
+    fn define_histogram(&mut self, stat_name: &str, stat_desc: &str) {
+        self.define(stat_name, exposition::Type::Histogram, stat_desc);
+    }
+
     fn define(
         &mut self,
         stat_name: &str,
-        stat_type: &str,
+        stat_type: exposition::Type,
+        stat_desc: &str,
+    ) {
+        if self.fahrenheit.is_some() {
+            if let Some(f) = fahrenheit_name(stat_name) {
+                let desc = stat_desc.replace("celsius", "fahrenheit");
+                self.define_one(&f, stat_type, &desc);
+                if self.fahrenheit == Some(config::Fahrenheit::Instead) {
+                    return;
+                }
+            }
+        }
+
+        self.define_one(stat_name, stat_type, stat_desc);
+    }
+
+    fn define_one(
+        &mut self,
+        stat_name: &str,
+        stat_type: exposition::Type,
         stat_desc: &str,
-        label_name: &str,
     ) {
+        /*
+         * Several field mappings may share a metric, in which case the first
+         * definition is used.
+         */
+        if self.typedefs.contains_key(stat_name) {
+            return;
+        }
+
         self.typedefs.insert(
             stat_name.to_string(),
-            EmitterStat {
-                name: stat_name.to_string(),
-                typ: stat_type.to_string(),
+            exposition::Family {
+                name: format!("{}{stat_name}", self.prefix),
+                typ: stat_type,
                 desc: stat_desc.to_string(),
-                label_name: label_name.to_string(),
+                samples: Vec::new(),
             },
         );
+        self.order.push(stat_name.to_string());
     }
 
-    fn emit_header(&mut self, stat_name: &str) {
-        if self.printed.contains(stat_name) {
+    /*
+     * Each sample may carry any number of labels, as name and value pairs.
+     * Some families, such as the process metrics, have only a single sample
+     * with no labels at all.
+     */
+    fn emit_sample(
+        &mut self,
+        stat_name: &str,
+        labels: &[(&str, &str)],
+        value: exposition::Point,
+        timestamp: Option<i64>,
+    ) {
+        let es = self.typedefs.get_mut(stat_name).unwrap();
+        if self.only.as_ref().is_some_and(|only| !only.contains(&es.name)) {
             return;
         }
 
-        let es = self.typedefs.get(stat_name).unwrap();
+        let mut labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>();
 
-        self.out += &format!("# TYPE {} {}\n", es.name, es.typ);
-        self.out += &format!("# HELP {} {}\n", es.name, es.desc);
+        /*
+         * The static labels from the configuration are added last, unless
+         * the sample already has a label of the same name.
+         */
+        for (k, v) in self.labels.iter() {
+            if !labels.iter().any(|(n, _)| n == k) {
+                labels.push((k.clone(), v.clone()));
+            }
+        }
 
-        self.printed.insert(stat_name.to_string());
+        es.samples.push(exposition::Sample { labels, value, timestamp });
     }
 
-    fn emit_i64(&mut self, stat_name: &str, label_value: &str, val: i64) {
-        self.emit_header(stat_name);
+    fn emit_i64(&mut self, stat_name: &str, labels: &[(&str, &str)], val: i64) {
+        let val = exposition::Point::Scalar(exposition::Value::I64(val));
+        self.emit_sample(stat_name, labels, val, None);
+    }
 
-        let es = self.typedefs.get(stat_name).unwrap();
-        self.out += &format!(
-            "{}{{{}=\"{}\"}}\t{}\n",
-            es.name, es.label_name, label_value, val
-        );
+    fn emit_u64(&mut self, stat_name: &str, labels: &[(&str, &str)], val: u64) {
+        let val = exposition::Point::Scalar(exposition::Value::U64(val));
+        self.emit_sample(stat_name, labels, val, None);
     }
 
-    fn emit_f32(&mut self, stat_name: &str, label_value: &str, val: f32) {
-        self.emit_header(stat_name);
+    fn emit_f64(&mut self, stat_name: &str, labels: &[(&str, &str)], val: f64) {
+        self.emit_f64_at(stat_name, labels, val, None);
+    }
 
-        let es = self.typedefs.get(stat_name).unwrap();
-        self.out += &format!(
-            "{}{{{}=\"{}\"}}\t{}\n",
-            es.name, es.label_name, label_value, val
-        );
+    fn emit_f64_at(
+        &mut self,
+        stat_name: &str,
+        labels: &[(&str, &str)],
+        val: f64,
+        timestamp: Option<i64>,
+    ) {
+        if self.fahrenheit.is_some() {
+            if let Some(f) = fahrenheit_name(stat_name) {
+                let fval = exposition::Point::Scalar(exposition::Value::F64(
+                    val * 9.0 / 5.0 + 32.0,
+                ));
+                self.emit_sample(&f, labels, fval, timestamp);
+                if self.fahrenheit == Some(config::Fahrenheit::Instead) {
+                    return;
+                }
+            }
+        }
+
+        let val = exposition::Point::Scalar(exposition::Value::F64(val));
+        self.emit_sample(stat_name, labels, val, timestamp);
+    }
+
+    fn emit_histogram(
+        &mut self,
+        stat_name: &str,
+        labels: &[(&str, &str)],
+        h: &exposition::Histogram,
+    ) {
+        let val = exposition::Point::Histogram(h.clone());
+        self.emit_sample(stat_name, labels, val, None);
+    }
+
+    /**
+     * Produce a response body that renders each metric family in the
+     * requested format only as the client is ready to receive it, rather than
+     * building the entire exposition in memory up front.
+     */
+    fn into_chunks(
+        self,
+        x: exposition::Exposition,
+        gzip: bool,
+    ) -> Box<dyn Iterator<Item = Vec<u8>> + Send> {
+        let Emitter { mut typedefs, order, .. } = self;
+
+        let families = order.into_iter().filter_map(move |name| {
+            Some(typedefs.remove(&name).unwrap())
+                .filter(|es| !es.samples.is_empty())
+        });
+        let families = x.render(families);
+
+        if gzip {
+            Box::new(exposition::gzip(families))
+        } else {
+            Box::new(families)
+        }
+    }
+
+    fn into_body(self, x: exposition::Exposition, gzip: bool) -> Body {
+        let chunks = self.into_chunks(x, gzip);
+        let chunks = chunks.map(Ok::<_, std::convert::Infallible>);
+        Body::wrap_stream(futures::stream::iter(chunks))
     }
+}
 
-    fn out(&self) -> &str {
-        self.out.as_str()
+fn sensor_location<'a>(c: &'a config::Config, id: &'a str) -> Option<&'a str> {
+    if let Some(location) = sdr::synthetic_location(id) {
+        return Some(location);
     }
+
+    c.sensor(id).map(|s| s.location.as_str())
 }
 
-#[endpoint {
-    method = GET,
-    path = "/metrics",
-}]
-async fn metrics(
-    rc: RequestContext<Arc<Main>>,
-) -> StdResult<Response<Body>, HttpError> {
-    let log = &rc.log;
-    let m = rc.context();
+/**
+ * Collect the current value of every metric.
+ */
+fn gather(
+    log: &Logger,
+    m: &Main,
+    c: &config::Config,
+    names: &BTreeSet<String>,
+) -> Emitter {
+    let start = Instant::now();
 
     // let mut k = m.kstat.lock().unwrap();
 
-    let mut e = Emitter::new();
+    let mut e = Emitter::new(c);
+    if !names.is_empty() {
+        e.only(names.clone());
+    }
+
+    e.define_gauge(
+        "tempexporter_source_up",
+        "input source is connected and being read",
+    );
+
+    for s in m.sources() {
+        e.emit_i64(
+            "tempexporter_source_up",
+            &[("source", s.name())],
+            s.up().into(),
+        );
+    }
+
+    if let Some(period) = c.silence() {
+        e.define_gauge(
+            "tempexporter_input_healthy",
+            "an input source has produced a record within silence_minutes",
+        );
+        e.emit_i64(
+            "tempexporter_input_healthy",
+            &[],
+            (!m.silent(period)).into(),
+        );
+    }
 
-    e.define(
-        "temperature_degrees_celsius",
-        "gauge",
-        "temperature in degrees celsius",
-        "location",
+    e.define_gauge(
+        "tempexporter_build_info",
+        "version and build information for this exporter",
+    );
+    e.emit_i64(
+        "tempexporter_build_info",
+        &[
+            ("version", env!("CARGO_PKG_VERSION")),
+            ("git_sha", env!("TEMPEXPORTER_GIT_SHA")),
+            ("rustc", env!("TEMPEXPORTER_RUSTC")),
+        ],
+        1,
     );
 
-    e.define(
-        "temperature_humidity_percent",
-        "gauge",
-        "relative humidity",
-        "location",
+    e.define_counter(
+        "tempexporter_scrapes_total",
+        "metrics requests served before this one",
     );
+    e.define_gauge(
+        "tempexporter_scrape_duration_seconds",
+        "time taken to gather metrics for the previous request",
+    );
+
+    {
+        let sc = m.scrapes.lock().unwrap();
+        e.emit_u64("tempexporter_scrapes_total", &[], sc.count);
+        if let Some(d) = sc.last_duration {
+            e.emit_f64(
+                "tempexporter_scrape_duration_seconds",
+                &[],
+                d.as_secs_f64(),
+            );
+        }
+    }
 
-    e.define(
-        "temperature_battery_ok",
-        "gauge",
-        "sensor battery health",
-        "location",
+    e.define_gauge(
+        "process_resident_memory_bytes",
+        "resident memory size in bytes",
+    );
+    e.define_counter(
+        "process_cpu_seconds_total",
+        "total user and system CPU time spent in seconds",
+    );
+    e.define_gauge("process_open_fds", "number of open file descriptors");
+    e.define_gauge(
+        "process_start_time_seconds",
+        "start time of the process since unix epoch in seconds",
     );
 
     {
-        for (id, r) in m.sdr.values() {
-            let location = match id.as_str() {
-                "acurite-tower-00005019-c" => "garage-door",
-                "acurite-tower-00007276-b" => "interior-door",
-                "acurite-tower-00011771-a" => "machine-room",
-                "acurite-tower-00011894-a" => "outside-awning",
-                "acurite-tower-00003109-a" => "conference-east",
-                _ => {
+        let ps = process::stats();
+        if let Some(rss) = ps.resident_bytes {
+            e.emit_u64("process_resident_memory_bytes", &[], rss);
+        }
+        if let Some(cpu) = ps.cpu_seconds {
+            e.emit_f64("process_cpu_seconds_total", &[], cpu);
+        }
+        if let Some(fds) = ps.open_fds {
+            e.emit_u64("process_open_fds", &[], fds);
+        }
+        e.emit_f64("process_start_time_seconds", &[], m.start_time);
+    }
+
+    e.define_counter(
+        "tempexporter_lines_total",
+        "lines read from the input source",
+    );
+    e.define_counter(
+        "tempexporter_parse_errors_total",
+        "lines that could not be parsed as a record",
+    );
+    e.define_counter(
+        "tempexporter_crlf_lines_total",
+        "lines that ended in a carriage return",
+    );
+    e.define_counter(
+        "tempexporter_invalid_utf8_lines_total",
+        "lines that contained invalid UTF-8",
+    );
+    e.define_counter(
+        "tempexporter_file_reopens_total",
+        "times the input file was reopened after being replaced or truncated",
+    );
+    e.define_gauge(
+        "tempexporter_read_offset_bytes",
+        "current read offset within the input file",
+    );
+    e.define_counter(
+        "tempexporter_unknown_sensor_readings_total",
+        "records from sensors not mapped to a location",
+    );
+    e.define_counter(
+        "tempexporter_spikes_discarded_total",
+        "records discarded by the spike filter",
+    );
+    e.define_counter(
+        "tempexporter_records_total",
+        "records accepted from the input source",
+    );
+
+    let mut records: BTreeMap<String, u64> = Default::default();
+    let mut unknown: BTreeMap<String, u64> = Default::default();
+    for s in m.sources() {
+        let source: &[(&str, &str)] = &[("source", s.name())];
+        let st = s.stats();
+        e.emit_u64("tempexporter_lines_total", source, st.lines);
+        e.emit_u64("tempexporter_parse_errors_total", source, st.parse_errors);
+        e.emit_u64("tempexporter_crlf_lines_total", source, st.crlf);
+        e.emit_u64(
+            "tempexporter_invalid_utf8_lines_total",
+            source,
+            st.invalid_utf8,
+        );
+        e.emit_u64("tempexporter_file_reopens_total", source, st.reopens);
+        e.emit_u64("tempexporter_read_offset_bytes", source, st.offset);
+        e.emit_u64("tempexporter_spikes_discarded_total", source, st.spikes);
+
+        /*
+         * Records from the same model or sensor may arrive through more than
+         * one source, so these are summed across all sources.
+         */
+        for (model, n) in st.records {
+            *records.entry(model).or_default() += n;
+        }
+        for (id, n) in st.unknown {
+            *unknown.entry(id).or_default() += n;
+        }
+    }
+    for (model, n) in records.iter() {
+        e.emit_u64("tempexporter_records_total", &[("model", model)], *n);
+    }
+    for (id, n) in unknown.iter() {
+        e.emit_u64(
+            "tempexporter_unknown_sensor_readings_total",
+            &[("id", id)],
+            *n,
+        );
+    }
+
+    {
+        let now_utc = chrono::Utc::now();
+
+        for f in c.fields() {
+            let typ = match f.typ {
+                config::MetricType::Gauge => exposition::Type::Gauge,
+                config::MetricType::Counter => exposition::Type::Counter,
+            };
+            e.define(&f.metric, typ, &f.help());
+        }
+
+        e.define_gauge(
+            "temperature_last_seen_timestamp_seconds",
+            "time at which the sensor was last heard, in seconds since epoch",
+        );
+
+        e.define_counter(
+            "temperature_sensor_messages_total",
+            "records received from the sensor since the exporter started",
+        );
+
+        e.define_gauge(
+            "temperature_reading_age_seconds",
+            "time since the sensor was last heard, in seconds",
+        );
+
+        e.define_gauge(
+            "temperature_min_celsius",
+            "lowest temperature received in degrees celsius",
+        );
+        e.define_gauge(
+            "temperature_max_celsius",
+            "highest temperature received in degrees celsius",
+        );
+        e.define_gauge(
+            "temperature_absolute_humidity_grams_per_cubic_meter",
+            "mass of water vapour per cubic metre of air",
+        );
+        if c.heat_index {
+            e.define_gauge(
+                "temperature_heat_index_celsius",
+                "heat index (apparent temperature) in degrees celsius",
+            );
+        }
+        if c.humidex {
+            e.define_gauge(
+                "temperature_humidex",
+                "humidex derived from temperature and humidity",
+            );
+        }
+
+        if c.histograms {
+            e.define_histogram(
+                "temperature_message_snr_db",
+                "signal-to-noise ratio of messages from the sensor",
+            );
+            e.define_histogram(
+                "temperature_message_interval_seconds",
+                "time between successive messages from the sensor",
+            );
+        }
+
+        if c.passthrough {
+            e.define_gauge(
+                "rtl433_field_value",
+                "numeric field from a device without a dedicated decoder",
+            );
+        }
+
+        let extremes = m.sensors.extremes();
+        let distributions = m.sensors.distributions();
+        for (id, r) in m.sensors.values().iter() {
+            let schema = c.schema(&r.model);
+
+            if c.passthrough && schema.is_none() {
+                /*
+                 * Emit every numeric field for devices we do not otherwise
+                 * understand, whether or not they have a mapped location.
+                 */
+                let sensor = r.label("id");
+                let channel = r.label("channel");
+                for field in r.fields.keys() {
+                    if field == "id" || field == "channel" {
+                        continue;
+                    }
+                    if let Some(v) = r.field(field) {
+                        e.emit_f64(
+                            "rtl433_field_value",
+                            &[
+                                ("model", &r.model),
+                                ("id", &sensor),
+                                ("channel", &channel),
+                                ("field", field),
+                            ],
+                            v,
+                        );
+                    }
+                }
+            }
+
+            let location = sensor_location(c, id);
+            if location.is_none() && c.identity_labels.is_none() {
+                if schema.is_some() {
                     warn!(log, "new temperature sensor? {id:?} -> {r:?}");
-                    continue;
                 }
+                continue;
+            }
+
+            /*
+             * Each sample for the sensor is labelled with its location, and
+             * any other labels from the sensor configuration.  If requested,
+             * the identity of the sensor is included as well, or instead; in
+             * that case, sensors without a location are not ignored.
+             */
+            let sensor = r.label("id");
+            let channel = r.label("channel");
+            let mut labels = Vec::new();
+            if c.identity_labels != Some(config::IdentityLabels::Instead) {
+                labels.extend(location.map(|l| ("location", l)));
+            }
+            if c.identity_labels.is_some() {
+                labels.push(("id", &sensor));
+                labels.push(("model", &r.model));
+                labels.push(("channel", &channel));
+            }
+            if sdr::synthetic_location(id).is_some() {
+                labels.push(("synthetic", "true"));
+            }
+            if let Some(s) = c.sensor(id) {
+                labels.extend(
+                    s.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+                );
+            }
+            let labels = labels.as_slice();
+
+            /*
+             * Samples are omitted once the sensor's readings are stale, if so
+             * configured for the metric family.
+             */
+            let age = r.received.elapsed();
+            let emits = |metric: &str| {
+                c.sensor(id).map(|s| s.emits(metric)).unwrap_or(true)
+                    && c.stale(metric).map(|d| age < d).unwrap_or(true)
             };
 
-            e.emit_f32(
-                "temperature_degrees_celsius",
-                location,
-                r.temperature_C,
+            /*
+             * Samples derived from the record itself may carry the time at
+             * which it was observed.
+             */
+            let ts =
+                c.metric_timestamps.then(|| r.observed().timestamp_millis());
+
+            for f in c.fields_for(&r.model) {
+                if !emits(&f.metric) {
+                    continue;
+                }
+                if let Some(v) = r.value(f) {
+                    e.emit_f64_at(&f.metric, labels, v, ts);
+                }
+            }
+
+            if emits("temperature_sensor_messages_total") {
+                e.emit_u64(
+                    "temperature_sensor_messages_total",
+                    labels,
+                    r.messages,
+                );
+            }
+
+            if let Some(d) = distributions.get(id) {
+                if d.snr.count() > 0 && emits("temperature_message_snr_db") {
+                    e.emit_histogram(
+                        "temperature_message_snr_db",
+                        labels,
+                        &d.snr,
+                    );
+                }
+                if d.interval.count() > 0
+                    && emits("temperature_message_interval_seconds")
+                {
+                    e.emit_histogram(
+                        "temperature_message_interval_seconds",
+                        labels,
+                        &d.interval,
+                    );
+                }
+            }
+
+            if let Some(x) = extremes.get(id) {
+                if emits("temperature_min_celsius") {
+                    e.emit_f64("temperature_min_celsius", labels, x.min);
+                }
+                if emits("temperature_max_celsius") {
+                    e.emit_f64("temperature_max_celsius", labels, x.max);
+                }
+            }
+
+            if let (Some(t), Some(rh)) =
+                (r.field("temperature_C"), r.field("humidity"))
+            {
+                let metric =
+                    "temperature_absolute_humidity_grams_per_cubic_meter";
+                if emits(metric) {
+                    e.emit_f64_at(
+                        metric,
+                        labels,
+                        derived::absolute_humidity(t, rh),
+                        ts,
+                    );
+                }
+                if c.heat_index && emits("temperature_heat_index_celsius") {
+                    e.emit_f64_at(
+                        "temperature_heat_index_celsius",
+                        labels,
+                        derived::heat_index(t, rh),
+                        ts,
+                    );
+                }
+                if c.humidex && emits("temperature_humidex") {
+                    e.emit_f64_at(
+                        "temperature_humidex",
+                        labels,
+                        derived::humidex(t, rh),
+                        ts,
+                    );
+                }
+            }
+
+            if let Some(t) = r.time {
+                if emits("temperature_last_seen_timestamp_seconds") {
+                    e.emit_f64(
+                        "temperature_last_seen_timestamp_seconds",
+                        labels,
+                        t.timestamp_millis() as f64 / 1000.0,
+                    );
+                }
+                if emits("temperature_reading_age_seconds") {
+                    let age = now_utc.signed_duration_since(t);
+                    e.emit_f64(
+                        "temperature_reading_age_seconds",
+                        labels,
+                        age.num_milliseconds() as f64 / 1000.0,
+                    );
+                }
+            }
+        }
+    }
+
+    if !c.alarm.is_empty() {
+        e.define_gauge(
+            "temperature_alarm_firing",
+            "rate-of-change alarm is firing",
+        );
+        e.define_gauge(
+            "temperature_alarm_silenced",
+            "alarm is silenced or within quiet hours",
+        );
+
+        let now = Instant::now();
+        let tod = chrono::Local::now().time();
+        let sensors = m.sensors.values();
+        let mut silences = m.silences.lock().unwrap();
+        for a in c.alarm.iter() {
+            /*
+             * An alarm fires if any sensor at the nominated location has
+             * changed temperature too quickly.
+             */
+            let firing = sensors
+                .iter()
+                .filter(|(id, _)| sensor_location(c, id) == Some(&a.location))
+                .any(|(id, _)| alarm::firing(a, &m.sensors.recent(id), now));
+
+            let silenced =
+                silences.silenced(&a.name, now) || alarm::quiet(c, a, tod);
+
+            let alarm: &[(&str, &str)] = &[("alarm", &a.name)];
+            e.emit_i64(
+                "temperature_alarm_firing",
+                alarm,
+                (firing && !silenced).into(),
             );
-            e.emit_f32("temperature_humidity_percent", location, r.humidity);
-            e.emit_i64("temperature_battery_ok", location, r.battery_ok);
+            e.emit_i64("temperature_alarm_silenced", alarm, silenced.into());
         }
     }
 
-    Ok(Response::builder()
-        .status(200)
-        .header("content-type", "text/plain")
-        .body(Body::from(e.out().to_string()))?)
+    {
+        let mut sc = m.scrapes.lock().unwrap();
+        sc.count += 1;
+        sc.last_duration = Some(start.elapsed());
+    }
+
+    e
 }
 
-async fn run(log: Logger, p: Matches, file: PathBuf) -> Result<()> {
-    let bind = p.opt_str("b").unwrap_or(String::from("0.0.0.0:4547"));
+/*
+ * Administrative endpoints, which change the state of the exporter or reveal
+ * its configuration, may be served on a separate listener from everything
+ * else; see "--admin-bind".
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Endpoints {
+    All,
+    Public,
+    Admin,
+}
 
+fn api_description(which: Endpoints) -> ApiDescription<Arc<Main>> {
     let mut api = ApiDescription::new();
-    api.register(metrics).unwrap();
+    if which != Endpoints::Admin {
+        api.register(endpoints::metrics).unwrap();
+        api.register(endpoints::index).unwrap();
+        api.register(endpoints::dashboard_page).unwrap();
+        api.register(endpoints::health).unwrap();
+        api.register(endpoints::livez).unwrap();
+        api.register(api::readings).unwrap();
+        api.register(api::sensors).unwrap();
+        api.register(api::stream).unwrap();
+        api.register(api::history).unwrap();
+        api.register(api::export).unwrap();
+        api.register(cors::readings).unwrap();
+        api.register(cors::sensors).unwrap();
+        api.register(cors::stream).unwrap();
+        api.register(cors::history).unwrap();
+        api.register(cors::export).unwrap();
+        api.register(endpoints::readyz).unwrap();
+        api.register(endpoints::openapi_description).unwrap();
+    }
+    if which != Endpoints::Public {
+        api.register(endpoints::alarm_silence).unwrap();
+        api.register(endpoints::inject).unwrap();
+        api.register(endpoints::reload).unwrap();
+        api.register(endpoints::config_get).unwrap();
+        api.register(endpoints::extremes_reset).unwrap();
+        api.register(api::sensor_delete).unwrap();
+    }
+    api
+}
+
+fn openapi_json(api: &ApiDescription<Arc<Main>>) -> Result<Bytes> {
+    let mut out = api
+        .openapi("tempexporter", env!("CARGO_PKG_VERSION"))
+        .description("Temperature readings received by rtl_433")
+        .json()
+        .map(|v| serde_json::to_vec_pretty(&v))??;
+    out.push(b'\n');
+    Ok(out.into())
+}
+
+/*
+ * The configuration shared by each HTTP server, and by the "unix" module as
+ * it serves requests on other sockets.
+ */
+fn server_config() -> ConfigDropshot {
+    ConfigDropshot::default()
+}
+
+async fn run(log: Logger, p: Matches) -> Result<()> {
+    /*
+     * If systemd passed us listening sockets, we need not listen on any
+     * other address unless asked to.
+     */
+    let activated = systemd::listeners()?;
+    let mut binds = p.opt_strs("b");
+    if binds.is_empty() && activated.is_empty() {
+        binds.push(String::from("0.0.0.0:4547"));
+    }
+
+    let openapi = openapi_json(&api_description(Endpoints::All))?;
+
+    /*
+     * The pid file remains locked until we exit.
+     */
+    let _pidfile = p
+        .opt_str("pidfile")
+        .map(|path| daemon::pidfile(&PathBuf::from(path)))
+        .transpose()?;
+
+    let creds = privilege::resolve(
+        p.opt_str("user").as_deref(),
+        p.opt_str("group").as_deref(),
+    )
+    .map_err(smf::ConfigError)?;
+
+    let admin = p
+        .opt_str("admin-bind")
+        .map(|a| {
+            let a = a.parse::<SocketAddr>()?;
+            if !a.ip().is_loopback() {
+                bail!("admin address {a} is not a loopback address");
+            }
+            Ok(a)
+        })
+        .transpose()?;
+    let public =
+        if admin.is_some() { Endpoints::Public } else { Endpoints::All };
 
-    let cfg =
-        ConfigDropshot { bind_address: bind.parse()?, ..Default::default() };
+    /*
+     * A separate HTTP server listens on each address.  Unix domain sockets,
+     * and sockets passed by systemd, are instead served by the "unix" module.
+     */
+    let mut sockets = Vec::new();
+    let mut addrs = Vec::new();
+    for b in binds {
+        if let Some(path) = b.strip_prefix("unix:") {
+            sockets.push(PathBuf::from(path));
+        } else {
+            addrs.push(b.parse::<SocketAddr>()?);
+        }
+    }
+    let socket_mode = p
+        .opt_str("socket-mode")
+        .map(|m| match u32::from_str_radix(&m, 8) {
+            Ok(m) if m <= 0o777 => Ok(m),
+            _ => Err(anyhow!("invalid socket mode {m:?}")),
+        })
+        .transpose()?;
+    if socket_mode.is_some() && sockets.is_empty() {
+        bail!("--socket-mode requires a Unix domain socket");
+    }
+    let client_ca = p.opt_str("tls-client-ca").map(PathBuf::from);
+    let tls = match (p.opt_str("tls-cert"), p.opt_str("tls-key")) {
+        (Some(cert), Some(key)) => {
+            Some(tls::config(cert.into(), key.into(), client_ca.as_deref())?)
+        }
+        (None, None) if client_ca.is_some() => {
+            bail!("--tls-client-ca requires --tls-cert and --tls-key");
+        }
+        (None, None) => None,
+        _ => bail!("--tls-cert and --tls-key must be specified together"),
+    };
+
+    let start_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs_f64();
+
+    let config_path = p.opt_str("c").map(PathBuf::from);
+    let config = Arc::new(if let Some(path) = &config_path {
+        config::Config::load(path).map_err(smf::ConfigError)?
+    } else {
+        config::Config::default()
+    });
+
+    /*
+     * Records from every data file are merged into a single set of sensors.
+     */
+    let sensors = sdr::Sensors::new(Arc::clone(&config));
+    if let Some(path) = &config.state_file {
+        match sensors.load(path) {
+            Ok(n) => info!(log, "loaded {n} sensors from {path:?}"),
+            Err(e) => warn!(log, "could not load saved sensors: {e}"),
+        }
+
+        let sensors0 = sensors.clone();
+        let path = path.clone();
+        let log0 = log.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                if let Err(e) = sensors0.save(&path) {
+                    error!(log0, "save sensors to {path:?}: {e}");
+                }
+            }
+        });
+    }
+    let checkpoint = config
+        .checkpoint
+        .as_ref()
+        .map(|path| {
+            let log = log.new(o!("component" => "checkpoint"));
+            checkpoint::Checkpoint::load(log, path).map(Arc::new)
+        })
+        .transpose()?;
+    let format =
+        p.opt_str("f").map(|f| f.parse()).transpose()?.unwrap_or_default();
+    let tails = watch::Tails::new(
+        log.clone(),
+        sensors.clone(),
+        checkpoint,
+        format,
+        &p.free,
+    )?;
+    let mut inputs = Vec::new();
+    if let Some(c) = &config.mqtt {
+        let log = log.new(o!("component" => "mqtt"));
+        inputs.push(mqtt::start(log, c, sensors.clone()));
+    }
+    if let Some(c) = &config.syslog {
+        let log = log.new(o!("component" => "syslog"));
+        inputs.push(syslog::start(log, c, sensors.clone()).await?);
+    }
+    if let Some(c) = &config.stream {
+        let log = log.new(o!("component" => "stream"));
+        inputs.push(stream::start(log, c, sensors.clone())?);
+    }
+    if let Some(c) = &config.child {
+        let log = log.new(o!("component" => "child"));
+        inputs.push(child::start(log, c, creds.clone(), sensors.clone()));
+    }
+    if p.free.is_empty() && inputs.is_empty() {
+        return Err(smf::ConfigError(anyhow!(
+            "specify data file name, or configure a network input"
+        ))
+        .into());
+    }
 
     let m = Arc::new(Main {
-        sdr: sdr::SdrTail::new(log.new(o!("component" => "sdrtail")), file)?,
+        sensors,
+        tails,
+        inputs,
+        config_path,
+        config: Mutex::new(config),
+        silences: Default::default(),
+        start_time,
+        started: Instant::now(),
+        scrapes: Default::default(),
+        cache: Default::default(),
+        openapi,
+        limiter: Default::default(),
+    });
+
+    /*
+     * Reload the configuration file whenever we receive SIGHUP.  The HTTP
+     * server and the data file tail continue undisturbed.
+     */
+    let mut hup = signal(SignalKind::hangup())?;
+    let m0 = Arc::clone(&m);
+    let log0 = log.clone();
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            match m0.reload() {
+                Ok(changes) => {
+                    info!(log0, "configuration reloaded"; "changes" => ?changes)
+                }
+                Err(e) => error!(log0, "configuration reload failed: {e}"),
+            }
+        }
+    });
+
+    /*
+     * If so configured, give up once the input has been silent for too long,
+     * rather than serving stale readings forever.
+     */
+    let m0 = Arc::clone(&m);
+    let log0 = log.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            let c = m0.config();
+            let Some(period) = c.silence().filter(|_| c.silence_exit) else {
+                continue;
+            };
+            if m0.silent(period) {
+                crit!(
+                    log0,
+                    "no record from any input source for {} minutes; exiting",
+                    period.as_secs() / 60,
+                );
+                std::process::exit(1);
+            }
+        }
+    });
+
+    /*
+     * Periodically look for new data files that match a directory or pattern
+     * given on the command line, and for files that have been removed.
+     */
+    let m0 = Arc::clone(&m);
+    let log0 = log.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            if let Err(e) = m0.tails.rescan() {
+                error!(log0, "rescan data files: {e}");
+            }
+        }
     });
 
-    let server = HttpServerStarter::new(&cfg, api, m, &log)
+    let https = tls.is_some();
+    let mut servers = Vec::new();
+    for bind_address in addrs {
+        let cfg = ConfigDropshot { bind_address, ..server_config() };
+        let server = HttpServerStarter::new_with_tls(
+            &cfg,
+            api_description(public),
+            Arc::clone(&m),
+            &log,
+            tls.clone(),
+        )
         .map_err(|e| anyhow!("server startup failure: {e:?}"))?;
+        servers.push(server.start());
+    }
 
-    info!(log, "listening on {:?}", cfg.bind_address);
-    let server_task = server.start();
+    for s in &servers {
+        info!(log, "listening on {:?}", s.local_addr(); "https" => https);
+    }
 
-    server_task.await.map_err(|e| anyhow!("failure to wait: {:?}", e))
+    let unix_sockets = !sockets.is_empty() || !activated.is_empty();
+    if unix_sockets {
+        let log = log.new(o!("component" => "unix"));
+        let handler = unix::handler(
+            &log,
+            &server_config(),
+            api_description(public),
+            Arc::clone(&m),
+        )
+        .await?;
+        let tls = tls.as_ref().map(unix::acceptor).transpose()?;
+        for path in &sockets {
+            unix::start(
+                log.clone(),
+                path,
+                socket_mode,
+                tls.clone(),
+                Arc::clone(&handler),
+            )
+            .await?;
+        }
+        if !activated.is_empty() {
+            info!(log, "listening on {} passed sockets", activated.len());
+        }
+        for l in activated {
+            unix::serve(log.clone(), l, tls.clone(), Arc::clone(&handler));
+        }
+    }
+
+    /*
+     * The admin listener is only reachable from the local system, so it does
+     * not use TLS.
+     */
+    if let Some(bind_address) = admin {
+        let cfg = ConfigDropshot { bind_address, ..server_config() };
+        let server = HttpServerStarter::new(
+            &cfg,
+            api_description(Endpoints::Admin),
+            Arc::clone(&m),
+            &log,
+        )
+        .map_err(|e| anyhow!("admin server startup failure: {e:?}"))?
+        .start();
+        info!(log, "admin endpoints listening on {:?}", server.local_addr());
+        servers.push(server);
+    }
+
+    /*
+     * Everything that might need privileges is now bound or open.
+     */
+    if let Some(creds) = creds {
+        creds.switch()?;
+        info!(log, "switched to {}", creds.describe());
+    }
+
+    /*
+     * Let systemd know that we are ready, and if it is watching over us,
+     * reassure it periodically for as long as we have an input source that
+     * is up.
+     */
+    if let Err(e) = systemd::notify("READY=1") {
+        warn!(log, "notify systemd: {e}");
+    }
+    daemon::ready()?;
+    if let Some(interval) = systemd::watchdog() {
+        let m0 = Arc::clone(&m);
+        let log0 = log.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval / 2).await;
+                if !m0.sources().iter().any(|s| s.up()) {
+                    warn!(
+                        log0,
+                        "no input source is up; not notifying watchdog"
+                    );
+                    continue;
+                }
+                if let Err(e) = systemd::notify("WATCHDOG=1") {
+                    warn!(log0, "notify systemd watchdog: {e}");
+                }
+            }
+        });
+    }
+
+    futures::future::try_join_all(servers)
+        .await
+        .map_err(|e| anyhow!("failure to wait: {:?}", e))?;
+
+    /*
+     * Sockets served by the "unix" module are served for as long as we run.
+     */
+    if unix_sockets {
+        std::future::pending::<()>().await;
+    }
+    Ok(())
 }
@@ -0,0 +1,66 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use slog::{info, warn, Logger};
+
+use crate::config::ConfigMqtt;
+use crate::format::Format;
+use crate::sdr::{Sensors, Source};
+
+/**
+ * Subscribe to the events that rtl_433 publishes to an MQTT broker, feeding
+ * each message to the same parser used for data files.
+ */
+pub fn start(log: Logger, c: &ConfigMqtt, sensors: Sensors) -> Source {
+    let name = format!("mqtt:{}:{}/{}", c.host, c.port, c.topic);
+    let source = Source::new(log.clone(), name, sensors, Format::Json);
+
+    let mut opts = MqttOptions::new(&c.client_id, &c.host, c.port);
+    opts.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &c.username {
+        opts.set_credentials(username, c.password.as_deref().unwrap_or(""));
+    }
+
+    let topic = c.topic.clone();
+    let source0 = source.clone();
+    tokio::spawn(async move {
+        let (client, mut eventloop) = AsyncClient::new(opts, 16);
+
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    /*
+                     * We use a clean session, so we must subscribe again
+                     * each time we connect to the broker.
+                     */
+                    info!(log, "connected; subscribing to {topic:?}");
+                    if let Err(e) =
+                        client.try_subscribe(&topic, QoS::AtMostOnce)
+                    {
+                        warn!(log, "subscribe to {topic:?}: {e}");
+                    }
+                    source0.set_up(true);
+                }
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    source0.ingest(&p.payload);
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    /*
+                     * The event loop reconnects on the next poll, so just
+                     * wait a little while to avoid spinning.
+                     */
+                    warn!(log, "connection error: {e}");
+                    source0.set_up(false);
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+            }
+        }
+    });
+
+    source
+}
@@ -0,0 +1,213 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Once we have bound our listening sockets and opened our files, which may
+ * require privileges, we can switch to an unprivileged user and group for
+ * the remainder of our life.  Note that anything opened later on, such as a
+ * data file that appears or is rotated, or the state file when it is saved,
+ * must then be accessible to that user.  A child process that we start
+ * before switching is started with the new credentials as well.
+ */
+
+use std::ffi::{CStr, CString};
+
+use anyhow::{bail, Result};
+
+#[derive(Clone)]
+pub struct Credentials {
+    user: Option<(CString, libc::uid_t)>,
+    gid: libc::gid_t,
+    /*
+     * The supplementary groups: those of which the user is a member, or just
+     * the new group if we are not switching user.
+     */
+    groups: Vec<libc::gid_t>,
+}
+
+/**
+ * Look up the user and group (each a name or a numeric ID) to which we
+ * should switch.  If no group is specified, the primary group of the user is
+ * used.
+ */
+pub fn resolve(
+    user: Option<&str>,
+    group: Option<&str>,
+) -> Result<Option<Credentials>> {
+    let user = user.map(lookup_user).transpose()?;
+    let gid = match (group, &user) {
+        (Some(group), _) => lookup_group(group)?,
+        (None, Some((_, _, gid))) => *gid,
+        (None, None) => return Ok(None),
+    };
+
+    let groups = match &user {
+        Some((name, _, _)) => group_list(name, gid)?,
+        None => vec![gid],
+    };
+
+    Ok(Some(Credentials {
+        user: user.map(|(name, uid, _)| (name, uid)),
+        gid,
+        groups,
+    }))
+}
+
+impl Credentials {
+    /**
+     * Switch to the user and group.  This must be done before any other
+     * thread could rely on our credentials, and cannot be undone.
+     */
+    pub fn switch(&self) -> Result<()> {
+        if let Err(e) = self.apply() {
+            bail!("switch to {}: {e}", self.describe());
+        }
+        Ok(())
+    }
+
+    /**
+     * Arrange for a child process to start with these credentials, if we
+     * have not yet switched to them ourselves.
+     */
+    pub fn command(&self, cmd: &mut tokio::process::Command) {
+        let creds = self.clone();
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::geteuid() == 0 {
+                    creds.apply()?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /*
+     * This is also called in a child process between fork and exec, so it
+     * must not allocate.  The supplementary groups must be set while we are
+     * still root.
+     */
+    fn apply(&self) -> std::io::Result<()> {
+        let n = self.groups.len() as _;
+        if unsafe { libc::setgroups(n, self.groups.as_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        if unsafe { libc::setgid(self.gid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        if let Some((_, uid)) = &self.user {
+            if unsafe { libc::setuid(*uid) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.user {
+            Some((name, uid)) => {
+                format!("user {:?} ({uid}), group {}", name, self.gid)
+            }
+            None => format!("group {}", self.gid),
+        }
+    }
+}
+
+fn lookup_user(user: &str) -> Result<(CString, libc::uid_t, libc::gid_t)> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut res = std::ptr::null_mut();
+
+    let r = if let Ok(uid) = user.parse::<libc::uid_t>() {
+        unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut res,
+            )
+        }
+    } else {
+        let name = CString::new(user)?;
+        unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut res,
+            )
+        }
+    };
+    if r != 0 {
+        bail!(
+            "look up user {user:?}: {}",
+            std::io::Error::from_raw_os_error(r)
+        );
+    }
+    if res.is_null() {
+        bail!("user {user:?} not found");
+    }
+
+    let name = unsafe { CStr::from_ptr(pwd.pw_name) }.to_owned();
+    Ok((name, pwd.pw_uid, pwd.pw_gid))
+}
+
+fn group_list(user: &CStr, gid: libc::gid_t) -> Result<Vec<libc::gid_t>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        let r = unsafe {
+            libc::getgrouplist(
+                user.as_ptr(),
+                gid as _,
+                groups.as_mut_ptr().cast(),
+                &mut n,
+            )
+        };
+        if r >= 0 {
+            groups.truncate(n as usize);
+            return Ok(groups);
+        }
+        if n as usize <= groups.len() {
+            bail!("look up groups of user {user:?}");
+        }
+        groups.resize(n as usize, 0);
+    }
+}
+
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 64 * 1024];
+    let mut res = std::ptr::null_mut();
+    let name = CString::new(group)?;
+
+    let r = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut res,
+        )
+    };
+    if r != 0 {
+        bail!(
+            "look up group {group:?}: {}",
+            std::io::Error::from_raw_os_error(r)
+        );
+    }
+    if res.is_null() {
+        bail!("group {group:?} not found");
+    }
+
+    Ok(grp.gr_gid)
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Resource usage for the exporter process itself, reported using the
+ * conventional Prometheus process metric names.  Each value is optional, as
+ * not every value can be determined on every platform.
+ */
+#[derive(Default)]
+pub struct ProcessStats {
+    pub resident_bytes: Option<u64>,
+    pub cpu_seconds: Option<f64>,
+    pub open_fds: Option<u64>,
+}
+
+/*
+ * The kernel reports CPU time in /proc/self/stat in units of USER_HZ, which
+ * is fixed at 100 as part of the Linux ABI.
+ */
+#[cfg(target_os = "linux")]
+const USER_HZ: f64 = 100.0;
+
+#[cfg(target_os = "linux")]
+pub fn stats() -> ProcessStats {
+    ProcessStats {
+        resident_bytes: linux_rss(),
+        cpu_seconds: linux_cpu(),
+        open_fds: open_fds(),
+    }
+}
+
+#[cfg(target_os = "illumos")]
+pub fn stats() -> ProcessStats {
+    ProcessStats {
+        resident_bytes: illumos_rss(),
+        cpu_seconds: illumos_cpu(),
+        open_fds: open_fds(),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "illumos")))]
+pub fn stats() -> ProcessStats {
+    ProcessStats { open_fds: open_fds(), ..Default::default() }
+}
+
+fn open_fds() -> Option<u64> {
+    let n = std::fs::read_dir("/proc/self/fd").ok()?.count();
+
+    /*
+     * Reading the directory itself requires a file descriptor, which we
+     * should not count.
+     */
+    Some(n.saturating_sub(1) as u64)
+}
+
+#[cfg(target_os = "linux")]
+fn linux_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let l = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+
+    let mut t = l["VmRSS:".len()..].split_whitespace();
+    let kb: u64 = t.next()?.parse().ok()?;
+    if t.next() != Some("kB") {
+        return None;
+    }
+
+    Some(kb * 1024)
+}
+
+#[cfg(target_os = "linux")]
+fn linux_cpu() -> Option<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+
+    /*
+     * The command name is enclosed in parentheses and may itself contain
+     * spaces or parentheses, so start after the last closing parenthesis.
+     * The user and system times are then the 12th and 13th fields.
+     */
+    let rest = &stat[stat.rfind(')')? + 1..];
+    let mut t = rest.split_whitespace().skip(11);
+    let utime: u64 = t.next()?.parse().ok()?;
+    let stime: u64 = t.next()?.parse().ok()?;
+
+    Some((utime + stime) as f64 / USER_HZ)
+}
+
+/*
+ * On illumos, /proc/self/psinfo and /proc/self/usage contain a psinfo_t and a
+ * prusage_t in the native layout; see proc(5).  We need only a few fields,
+ * which are at these offsets for a 64-bit process:
+ */
+#[cfg(target_os = "illumos")]
+const PSINFO_RSSIZE: usize = 56;
+#[cfg(target_os = "illumos")]
+const PRUSAGE_UTIME: usize = 72;
+#[cfg(target_os = "illumos")]
+const PRUSAGE_STIME: usize = 88;
+
+#[cfg(target_os = "illumos")]
+fn native_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(buf.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(target_os = "illumos")]
+fn illumos_rss() -> Option<u64> {
+    let psinfo = std::fs::read("/proc/self/psinfo").ok()?;
+
+    /*
+     * The resident set size is in kilobytes.
+     */
+    Some(native_u64(&psinfo, PSINFO_RSSIZE)? * 1024)
+}
+
+#[cfg(target_os = "illumos")]
+fn illumos_cpu() -> Option<f64> {
+    let usage = std::fs::read("/proc/self/usage").ok()?;
+
+    /*
+     * Each time is a timestruc_t, of seconds and then nanoseconds.
+     */
+    let time = |offset| {
+        let secs = native_u64(&usage, offset)?;
+        let nsecs = native_u64(&usage, offset + 8)?;
+        Some(secs as f64 + nsecs as f64 / 1e9)
+    };
+
+    Some(time(PRUSAGE_UTIME)? + time(PRUSAGE_STIME)?)
+}
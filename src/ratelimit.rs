@@ -0,0 +1,128 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    result::Result as StdResult,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use dropshot::{HttpError, RequestContext};
+use hyper::StatusCode;
+
+use crate::config::ConfigRateLimit;
+use crate::Main;
+
+/*
+ * Once we are tracking this many clients, we forget any that have not made a
+ * request for long enough to have regained their full allowance.  If that is
+ * not enough, we forget those seen least recently until we are tracking no
+ * more than CLIENTS_LOW, so that the cost of doing so is shared by many new
+ * clients.  A client that is forgotten early gets its full allowance back.
+ */
+const CLIENTS_MAX: usize = 1024;
+const CLIENTS_LOW: usize = CLIENTS_MAX * 3 / 4;
+
+/**
+ * Limits the rate of requests from each client, by IP address.  Each client
+ * has an allowance of requests that is used up by each request and
+ * replenished steadily over time, up to the configured limit.
+ */
+#[derive(Default)]
+pub struct Limiter {
+    clients: Mutex<HashMap<IpAddr, Allowance>>,
+}
+
+struct Allowance {
+    requests: f64,
+    when: Instant,
+}
+
+impl Limiter {
+    fn allow(&self, c: &ConfigRateLimit, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let max = f64::from(c.requests);
+        let rate = max / c.seconds as f64;
+        let current = |a: &Allowance| {
+            let t = now.saturating_duration_since(a.when).as_secs_f64();
+            (a.requests + t * rate).min(max)
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= CLIENTS_MAX && !clients.contains_key(&ip) {
+            clients.retain(|_, a| current(a) < max);
+            if clients.len() > CLIENTS_LOW {
+                let n = clients.len() - CLIENTS_LOW;
+                let mut when =
+                    clients.values().map(|a| a.when).collect::<Vec<_>>();
+                let (_, &mut cutoff, _) = when.select_nth_unstable(n - 1);
+                clients.retain(|_, a| a.when > cutoff);
+            }
+        }
+
+        let a =
+            clients.entry(ip).or_insert(Allowance { requests: max, when: now });
+        a.requests = current(a);
+        a.when = now;
+        if a.requests < 1.0 {
+            return false;
+        }
+        a.requests -= 1.0;
+        true
+    }
+}
+
+/**
+ * Confirm that the client has not exceeded the configured rate limit, if
+ * any.
+ */
+pub fn check(rc: &RequestContext<Arc<Main>>) -> StdResult<(), HttpError> {
+    let m = rc.context();
+    let c = m.config();
+    let Some(rl) = &c.rate_limit else {
+        return Ok(());
+    };
+
+    if m.limiter.allow(rl, rc.request.remote_addr().ip()) {
+        return Ok(());
+    }
+
+    Err(HttpError::for_client_error(
+        None,
+        StatusCode::TOO_MANY_REQUESTS,
+        "too many requests".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn evict_least_recent() {
+        let l = Limiter::default();
+        let c = ConfigRateLimit { requests: 10, seconds: 3600 };
+        let ip = |n: usize| IpAddr::from(Ipv6Addr::from(n as u128));
+
+        /*
+         * Every client is still using its allowance, so none can be
+         * forgotten for having regained it.
+         */
+        for n in 0..CLIENTS_MAX {
+            assert!(l.allow(&c, ip(n)));
+        }
+        assert!(l.allow(&c, ip(0)));
+        assert_eq!(l.clients.lock().unwrap().len(), CLIENTS_MAX);
+
+        assert!(l.allow(&c, ip(CLIENTS_MAX)));
+        let clients = l.clients.lock().unwrap();
+        assert!(clients.len() <= CLIENTS_LOW + 1);
+        assert!(clients.contains_key(&ip(0)));
+        assert!(clients.contains_key(&ip(CLIENTS_MAX)));
+        assert!(!clients.contains_key(&ip(1)));
+    }
+}
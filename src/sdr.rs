@@ -1,117 +1,1800 @@
 use std::{
-    collections::BTreeMap,
-    io::{Read, Seek},
-    os::unix::fs::MetadataExt,
-    path::PathBuf,
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    io::BufRead,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Result};
-use serde::Deserialize;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use slog::{error, info, warn, Logger};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::{broadcast, watch};
 
+use crate::checkpoint::{Checkpoint, Position};
+use crate::config::{Config, ConfigField};
+use crate::exposition::Histogram;
+use crate::format::{Decoder, Format};
+use crate::lines::{Line, LineSplitter};
+
+/**
+ * The most recent record from each sensor, shared by every input source.  The
+ * lock is only ever held briefly, and never across an await point, so a
+ * regular mutex is fine even from within async tasks.
+ */
+#[derive(Clone)]
+pub struct Sensors(Arc<Mutex<Locked>>);
+
+/**
+ * Follows a file to which rtl_433 is appending JSON records, in a task on the
+ * tokio runtime.
+ */
 #[derive(Clone)]
 pub struct SdrTail(Arc<Inner>);
 
-#[derive(Clone, Deserialize)]
-#[allow(unused)]
-pub struct RecordBase {
-    time: String,
-    model: String,
+/**
+ * An input from which records are received, such as a file or a message
+ * broker, along with counters that describe its health.
+ */
+#[derive(Clone)]
+pub struct Source(Arc<SourceInner>);
+
+#[derive(Clone, Deserialize)]
+#[allow(unused)]
+pub struct RecordBase {
+    time: Value,
+    model: String,
+}
+
+/*
+ * Records are retained for every model, along with the complete set of
+ * fields from the original JSON object.  The mapping from fields to metrics
+ * is described by the schema in the configuration.
+ */
+#[derive(Clone, Debug)]
+pub struct Record {
+    /*
+     * The time at which rtl_433 received the record, if it could be parsed:
+     */
+    pub time: Option<DateTime<Utc>>,
+    pub model: String,
+    pub fields: Map<String, Value>,
+    /*
+     * Monotonic totals maintained for counter fields that wrap around:
+     */
+    pub totals: BTreeMap<String, f64>,
+    /*
+     * Moving averages maintained for smoothed fields, by metric name:
+     */
+    pub smoothed: BTreeMap<String, f64>,
+    /*
+     * The number of records received from this sensor since the exporter
+     * started, including this one:
+     */
+    pub messages: u64,
+    pub received: Instant,
+}
+
+impl Record {
+    /**
+     * Look up a numeric field using a simple path expression: field names
+     * separated by dots (e.g., "wind_dir_deg" or "extra.moisture").  Boolean
+     * values are treated as 0 or 1.
+     */
+    pub fn field(&self, path: &str) -> Option<f64> {
+        let mut names = path.split('.');
+        let mut v = self.fields.get(names.next()?)?;
+        for name in names {
+            v = v.as_object()?.get(name)?;
+        }
+
+        match v {
+            Value::Number(n) => n.as_f64(),
+            Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    /**
+     * Determine the value to emit for a particular field mapping.
+     */
+    pub fn value(&self, f: &ConfigField) -> Option<f64> {
+        if f.wrap.is_some() || f.resets {
+            self.totals.get(&f.path).copied()
+        } else if f.smooth.is_some() {
+            self.smoothed.get(&f.metric).copied()
+        } else {
+            self.field(&f.path)
+        }
+    }
+
+    /**
+     * Determine the value of each metric, by metric name.  As in the metrics
+     * output, the first field mapping that provides a value for a metric is
+     * the one we use.
+     */
+    pub fn metrics(&self, c: &Config) -> BTreeMap<String, f64> {
+        let mut values = BTreeMap::new();
+        for f in c.fields_for(&self.model) {
+            if let Some(v) = self.value(f) {
+                values.entry(f.metric.clone()).or_insert(v);
+            }
+        }
+        values
+    }
+
+    /**
+     * Render a top-level field as a label value, or return an empty string if
+     * the field is not present.
+     */
+    pub fn label(&self, name: &str) -> String {
+        match self.fields.get(name) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(v) => v.to_string(),
+        }
+    }
+
+    pub fn temperature(&self) -> Option<f32> {
+        self.field("temperature_C").map(|t| t as f32)
+    }
+
+    /**
+     * The time at which the record was observed: the time reported by
+     * rtl_433 if we have it, or otherwise the time we received the record.
+     */
+    pub fn observed(&self) -> DateTime<Utc> {
+        self.time.unwrap_or_else(|| {
+            Utc::now()
+                - chrono::Duration::from_std(self.received.elapsed())
+                    .unwrap_or_default()
+        })
+    }
+}
+
+/*
+ * The form in which records are saved to the state file.  As an Instant
+ * cannot be saved, the time at which the record was received is stored as a
+ * wall clock time instead.
+ */
+#[derive(Serialize, Deserialize)]
+struct SavedRecord {
+    time: Option<DateTime<Utc>>,
+    model: String,
+    fields: Map<String, Value>,
+    totals: BTreeMap<String, f64>,
+    smoothed: BTreeMap<String, f64>,
+    received: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub when: Instant,
+    pub temperature: f32,
+}
+
+/**
+ * The lowest and highest temperature received from a sensor since the
+ * exporter started, or since the extremes were last reset.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Extremes {
+    pub min: f64,
+    pub max: f64,
+}
+
+/**
+ * The distribution of some properties of the messages received from a sensor
+ * since the exporter started.
+ */
+#[derive(Clone, Debug)]
+pub struct Distributions {
+    /*
+     * The signal-to-noise ratio, in decibels:
+     */
+    pub snr: Histogram,
+    /*
+     * The time between successive messages, in seconds:
+     */
+    pub interval: Histogram,
+}
+
+const SNR_BOUNDS: &[f64] = &[3.0, 6.0, 9.0, 12.0, 15.0, 20.0, 25.0, 30.0];
+const INTERVAL_BOUNDS: &[f64] =
+    &[15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0];
+
+impl Distributions {
+    fn new() -> Distributions {
+        Distributions {
+            snr: Histogram::new(SNR_BOUNDS),
+            interval: Histogram::new(INTERVAL_BOUNDS),
+        }
+    }
+}
+
+/**
+ * What we know about a sensor from which we have received a record since the
+ * exporter started, whether or not it is mapped to a location, and even once
+ * its readings have expired.
+ */
+#[derive(Clone, Debug)]
+pub struct Seen {
+    pub model: String,
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    pub messages: u64,
+}
+
+/**
+ * A point in the history of a sensor: either a single record, or the average
+ * of those received over an interval that begins at this time.
+ */
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    pub values: BTreeMap<String, f64>,
+}
+
+/*
+ * The history of a sensor: its most recent records and, if so configured, the
+ * averages of older ones for each downsampling interval, finest first.
+ */
+#[derive(Default)]
+struct History {
+    records: VecDeque<Arc<Record>>,
+    averages: Vec<VecDeque<Average>>,
+}
+
+struct Average {
+    start: DateTime<Utc>,
+    /*
+     * The sum and number of values for each metric:
+     */
+    values: BTreeMap<String, (f64, u64)>,
+}
+
+impl History {
+    /*
+     * Discard, or fold into the averages for the next downsampling interval,
+     * anything that has become too old to keep as it is.
+     */
+    fn prune(&mut self, c: &Config, period: Duration) {
+        let now = Utc::now();
+        let ds = &c.downsample;
+        self.averages.resize_with(ds.len(), Default::default);
+
+        let limit = ds.first().map_or(period, |d| d.after());
+        while let Some(r) = self.records.front() {
+            let age = r.received.elapsed();
+            if self.records.len() <= c.history_max && age <= limit {
+                break;
+            }
+
+            let r = self.records.pop_front().unwrap();
+            if let Some(d) = ds.first().filter(|_| age <= period) {
+                let values = r.metrics(c).into_iter().map(|(m, v)| (m, (v, 1)));
+                fold(
+                    &mut self.averages[0],
+                    align(r.observed(), d.interval()),
+                    values,
+                );
+            }
+        }
+
+        for i in 0..ds.len() {
+            let limit = ds.get(i + 1).map_or(period, |d| d.after());
+            while let Some(a) = self.averages[i].front() {
+                let age = average_age(now, a, ds[i].interval());
+                if age <= limit {
+                    break;
+                }
+
+                let a = self.averages[i].pop_front().unwrap();
+                if let Some(d) = ds.get(i + 1).filter(|_| age <= period) {
+                    fold(
+                        &mut self.averages[i + 1],
+                        align(a.start, d.interval()),
+                        a.values,
+                    );
+                }
+            }
+        }
+    }
+
+    /*
+     * List everything within the history period, oldest first.  Records are
+     * otherwise only discarded as new ones arrive, so we must skip any that
+     * have aged out since.
+     */
+    fn entries(&self, c: &Config, period: Duration) -> Vec<HistoryEntry> {
+        let now = Utc::now();
+        let mut out = Vec::new();
+
+        for (a, d) in self.averages.iter().zip(c.downsample.iter()).rev() {
+            out.extend(
+                a.iter()
+                    .filter(|a| average_age(now, a, d.interval()) <= period)
+                    .map(|a| HistoryEntry {
+                        time: a.start,
+                        values: a
+                            .values
+                            .iter()
+                            .map(|(m, (sum, n))| (m.clone(), sum / *n as f64))
+                            .collect(),
+                    }),
+            );
+        }
+        out.extend(
+            self.records.iter().filter(|r| r.received.elapsed() <= period).map(
+                |r| HistoryEntry { time: r.observed(), values: r.metrics(c) },
+            ),
+        );
+
+        out
+    }
+}
+
+/*
+ * Add values to the average for the interval that begins at "start", which
+ * is either the latest interval or one that follows it.
+ */
+fn fold(
+    averages: &mut VecDeque<Average>,
+    start: DateTime<Utc>,
+    values: impl IntoIterator<Item = (String, (f64, u64))>,
+) {
+    let a = match averages.back_mut() {
+        Some(a) if a.start == start => a,
+        _ => {
+            averages.push_back(Average { start, values: BTreeMap::new() });
+            averages.back_mut().unwrap()
+        }
+    };
+
+    for (m, (sum, n)) in values {
+        let v = a.values.entry(m).or_insert((0.0, 0));
+        v.0 += sum;
+        v.1 += n;
+    }
+}
+
+/*
+ * Find the start of the interval in which a time falls.  Intervals are
+ * aligned to the epoch, so that those for each downsampling interval nest
+ * within those of the next.
+ */
+fn align(t: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    let secs = interval.as_secs().max(1) as i64;
+    DateTime::from_timestamp(t.timestamp().div_euclid(secs) * secs, 0)
+        .unwrap_or(t)
+}
+
+/*
+ * How long ago the interval covered by an average ended:
+ */
+fn average_age(
+    now: DateTime<Utc>,
+    a: &Average,
+    interval: Duration,
+) -> Duration {
+    let end =
+        a.start + chrono::Duration::from_std(interval).unwrap_or_default();
+    (now - end).to_std().unwrap_or_default()
+}
+
+/*
+ * Synthetic readings injected through the API are stored under an ID derived
+ * from their location, rather than from any particular sensor.
+ */
+const SYNTHETIC_PREFIX: &str = "synthetic-";
+
+pub fn synthetic_location(id: &str) -> Option<&str> {
+    id.strip_prefix(SYNTHETIC_PREFIX)
+}
+
+/*
+ * Sensors are identified by model, ID, and (if present) channel; e.g.,
+ * "acurite-tower-00005019-c".
+ */
+fn sensor_id(fields: &Map<String, Value>, model: &str) -> Option<String> {
+    let mut id = match fields.get("id")? {
+        Value::Number(n) => match n.as_u64() {
+            Some(n) => format!("{}-{:08}", model, n),
+            None => format!("{}-{}", model, n),
+        },
+        Value::String(s) => format!("{}-{}", model, s),
+        _ => return None,
+    };
+
+    match fields.get("channel") {
+        Some(Value::Number(n)) => id += &format!("-{n}"),
+        Some(Value::String(s)) => id += &format!("-{s}"),
+        _ => (),
+    }
+
+    Some(id.to_lowercase())
+}
+
+/*
+ * rtl_433 encodes the unit of a value in the field name; e.g., "temperature_F"
+ * or "wind_avg_mi_h".  Each entry in this table maps a unit suffix to the
+ * suffix for the canonical unit, with the scale and offset required to
+ * convert the value.  Longer suffixes must appear before any shorter suffix
+ * that would also match.
+ */
+const UNITS: &[(&str, &str, f64, f64)] = &[
+    ("_F", "_C", 5.0 / 9.0, -32.0 * 5.0 / 9.0),
+    ("_kPa", "_Pa", 1000.0, 0.0),
+    ("_hPa", "_Pa", 100.0, 0.0),
+    ("_PSI", "_Pa", 6894.757, 0.0),
+    ("_bar", "_Pa", 100000.0, 0.0),
+    ("_inHg", "_Pa", 3386.389, 0.0),
+    ("_km_h", "_m_s", 1.0 / 3.6, 0.0),
+    ("_mi_h", "_m_s", 0.44704, 0.0),
+    ("_mph", "_m_s", 0.44704, 0.0),
+    ("_kn", "_m_s", 1852.0 / 3600.0, 0.0),
+    ("_in_h", "_mm_h", 25.4, 0.0),
+    ("_in", "_mm", 25.4, 0.0),
+    ("_mV", "_V", 0.001, 0.0),
+];
+
+/*
+ * Replace any numeric field with a non-canonical unit suffix with the
+ * equivalent field in the canonical unit, so that a mixed fleet of devices
+ * produces consistent values.  If a device reports both forms of a field, we
+ * keep the value it reported in the canonical unit.
+ */
+fn normalize_units(fields: &mut Map<String, Value>) {
+    let convert = fields
+        .iter()
+        .filter_map(|(k, v)| {
+            let v = v.as_f64()?;
+            UNITS.iter().find_map(|(suffix, canonical, scale, offset)| {
+                let base = k.strip_suffix(suffix)?;
+                Some((
+                    k.clone(),
+                    format!("{base}{canonical}"),
+                    v * scale + offset,
+                ))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (k, canonical, v) in convert {
+        fields.remove(&k);
+        if !fields.contains_key(&canonical) {
+            if let Some(n) = serde_json::Number::from_f64(v) {
+                fields.insert(canonical, Value::Number(n));
+            }
+        }
+    }
+}
+
+/*
+ * By default, rtl_433 reports the time at which each record was received as a
+ * local time string; e.g., "2024-03-01 12:34:56".  With "-M time:iso" the
+ * date and time are instead separated by a "T", and with "-M time:iso:tz" an
+ * offset from UTC is appended; e.g., "2024-03-01T12:34:56+1100".  With "-M
+ * time:unix" the time is in seconds since the epoch, possibly with a
+ * fractional part; e.g., "1709257496.123".
+ */
+fn parse_time(time: &Value) -> Option<DateTime<Utc>> {
+    let time = match time {
+        Value::String(s) => s.trim(),
+        Value::Number(n) => {
+            return unix_time(&n.to_string())
+                .or_else(|| unix_time_f64(n.as_f64()?));
+        }
+        _ => return None,
+    };
+
+    if time.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return unix_time(time);
+    }
+
+    for fmt in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(dt) = DateTime::parse_from_str(time, fmt) {
+            return Some(dt.with_timezone(&Utc));
+        }
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(time) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(time, fmt).ok())?;
+    Some(Local.from_local_datetime(&naive).earliest()?.with_timezone(&Utc))
+}
+
+/*
+ * Seconds since the epoch are parsed exactly where possible, as a double does
+ * not have the precision to represent the current time to the millisecond.
+ */
+fn unix_time(time: &str) -> Option<DateTime<Utc>> {
+    let (secs, frac) = time.split_once('.').unwrap_or((time, ""));
+    if secs.is_empty()
+        || !secs.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let nanos = format!("{frac:0<9}")[..9].parse().ok()?;
+    DateTime::from_timestamp(secs.parse().ok()?, nanos)
+}
+
+fn unix_time_f64(secs: f64) -> Option<DateTime<Utc>> {
+    if !secs.is_finite() || secs < 0.0 {
+        return None;
+    }
+    let whole = secs.trunc();
+    let nanos = ((secs - whole) * 1e9).round().min(999_999_999.0) as u32;
+    DateTime::from_timestamp(whole as i64, nanos)
+}
+
+/*
+ * Lines may end with a carriage return if written by a program on Windows,
+ * and a corrupted transmission occasionally produces invalid UTF-8 in a
+ * string field.  Neither should prevent us from using the rest of the record,
+ * so we strip the carriage return and replace any invalid sequences.
+ */
+struct Cleaned<'a> {
+    line: Cow<'a, [u8]>,
+    crlf: bool,
+    invalid_utf8: bool,
+}
+
+fn clean(line: &[u8]) -> Cleaned<'_> {
+    let (line, crlf) = match line.strip_suffix(b"\r") {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+
+    match String::from_utf8_lossy(line) {
+        Cow::Borrowed(_) => {
+            Cleaned { line: Cow::Borrowed(line), crlf, invalid_utf8: false }
+        }
+        Cow::Owned(s) => Cleaned {
+            line: Cow::Owned(s.into_bytes()),
+            crlf,
+            invalid_utf8: true,
+        },
+    }
+}
+
+fn from_fields(
+    mut fields: Map<String, Value>,
+) -> Result<Option<(String, Record)>> {
+    normalize_units(&mut fields);
+
+    let rb: RecordBase = serde_json::from_value(Value::Object(fields.clone()))?;
+
+    let Some(id) = sensor_id(&fields, &rb.model) else {
+        /*
+         * We cannot track readings from devices that do not identify
+         * themselves.
+         */
+        return Ok(None);
+    };
+
+    Ok(Some((
+        id,
+        Record {
+            time: parse_time(&rb.time),
+            model: rb.model,
+            fields,
+            totals: Default::default(),
+            smoothed: Default::default(),
+            messages: 1,
+            received: Instant::now(),
+        },
+    )))
+}
+
+/**
+ * Determine the increase in a device counter between two successive readings.
+ * We cannot detect more than a full cycle of a counter that wraps between two
+ * messages, but devices transmit frequently enough that this should not
+ * matter in practice.  A counter that does not wrap instead starts again from
+ * zero, so any decrease means the whole of the new value is an increase.
+ */
+pub fn counter_increase(prev: f64, new: f64, wrap: Option<f64>) -> f64 {
+    match wrap {
+        Some(wrap) => (new - prev).rem_euclid(wrap),
+        None if new < prev => new,
+        None => new - prev,
+    }
+}
+
+impl Sensors {
+    pub fn new(config: Arc<Config>) -> Sensors {
+        Sensors(Arc::new(Mutex::new(Locked {
+            config,
+            current: Default::default(),
+            recent: Default::default(),
+            extremes: Default::default(),
+            extremes_day: Local::now().date_naive(),
+            distributions: Default::default(),
+            seen: Default::default(),
+            history: Default::default(),
+            events: broadcast::channel(EVENTS_MAX).0,
+        })))
+    }
+
+    fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.0.lock().unwrap().config)
+    }
+
+    /**
+     * Save the most recent record from each sensor, so that they can be
+     * loaded again after a restart.
+     */
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let now = Instant::now();
+        let now_utc = Utc::now();
+
+        let saved = self
+            .0
+            .lock()
+            .unwrap()
+            .current
+            .iter()
+            .map(|(id, r)| {
+                let age = now.saturating_duration_since(r.received);
+                let received = now_utc
+                    - chrono::Duration::from_std(age).unwrap_or_default();
+                let sr = SavedRecord {
+                    time: r.time,
+                    model: r.model.clone(),
+                    fields: r.fields.clone(),
+                    totals: r.totals.clone(),
+                    smoothed: r.smoothed.clone(),
+                    received,
+                };
+                (id.clone(), sr)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        /*
+         * Write to a temporary file and rename it into place, so that the
+         * state file is never left partially written.
+         */
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&saved)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /**
+     * Load records saved by a previous process.  Each record retains the
+     * time at which it was originally received, so that it expires as it
+     * would have had we not restarted.
+     */
+    pub fn load(&self, path: &Path) -> Result<usize> {
+        let buf = match std::fs::read(path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => bail!("read state {path:?}: {e}"),
+        };
+        let saved: BTreeMap<String, SavedRecord> =
+            match serde_json::from_slice(&buf) {
+                Ok(saved) => saved,
+                Err(e) => bail!("parse state {path:?}: {e}"),
+            };
+
+        let now = Instant::now();
+        let now_utc = Utc::now();
+
+        let mut l = self.0.lock().unwrap();
+        let mut count = 0;
+        for (id, sr) in saved {
+            let Ok(age) = now_utc.signed_duration_since(sr.received).to_std()
+            else {
+                continue;
+            };
+            let Some(received) = now.checked_sub(age) else {
+                continue;
+            };
+
+            Arc::make_mut(&mut l.current).entry(id).or_insert_with(|| {
+                Arc::new(Record {
+                    time: sr.time,
+                    model: sr.model,
+                    fields: sr.fields,
+                    totals: sr.totals,
+                    smoothed: sr.smoothed,
+                    messages: 0,
+                    received,
+                })
+            });
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /**
+     * Return the most recent record from each sensor.  This is a snapshot,
+     * shared with the input sources until they next store a record, so it is
+     * cheap to obtain and holding it does not block them.
+     */
+    pub fn values(&self) -> Arc<BTreeMap<String, Arc<Record>>> {
+        let mut l = self.0.lock().unwrap();
+        l.expire();
+
+        Arc::clone(&l.current)
+    }
+
+    pub fn inject(
+        &self,
+        location: &str,
+        temperature: f32,
+        humidity: f32,
+        battery_ok: i64,
+    ) {
+        let mut fields = Map::new();
+        fields.insert("temperature_C".into(), temperature.into());
+        fields.insert("humidity".into(), humidity.into());
+        fields.insert("battery_ok".into(), battery_ok.into());
+
+        let r = Record {
+            time: Some(Utc::now()),
+            model: "synthetic".into(),
+            fields,
+            totals: Default::default(),
+            smoothed: Default::default(),
+            messages: 1,
+            received: Instant::now(),
+        };
+
+        let id = format!("{SYNTHETIC_PREFIX}{location}");
+        self.0.lock().unwrap().record(id, r, true);
+    }
+
+    /**
+     * Use a new configuration; e.g., after a reload.  This affects the
+     * period for which recent samples are retained, and the handling of
+     * subsequent records.
+     */
+    pub fn set_config(&self, config: Arc<Config>) {
+        let mut l = self.0.lock().unwrap();
+        if config.history().is_none() {
+            l.history.clear();
+        } else if config.downsample != l.config.downsample {
+            /*
+             * The existing averages may not line up with the new intervals.
+             */
+            for h in l.history.values_mut() {
+                h.averages.clear();
+            }
+        }
+        l.config = config;
+    }
+
+    /**
+     * Return the temperature extremes for each sensor.
+     */
+    pub fn extremes(&self) -> BTreeMap<String, Extremes> {
+        let mut l = self.0.lock().unwrap();
+        l.rollover();
+        l.extremes.clone()
+    }
+
+    /**
+     * Forget everything about a sensor, as if we had never received a record
+     * from it; e.g., once it has been retired.  Returns false if the sensor
+     * was not known.
+     */
+    pub fn remove(&self, id: &str) -> bool {
+        let mut l = self.0.lock().unwrap();
+        let l = &mut *l;
+
+        let found = l.current.contains_key(id) || l.seen.contains_key(id);
+        if l.current.contains_key(id) {
+            Arc::make_mut(&mut l.current).remove(id);
+        }
+        l.recent.remove(id);
+        l.extremes.remove(id);
+        l.distributions.remove(id);
+        l.seen.remove(id);
+        l.history.remove(id);
+        found
+    }
+
+    /**
+     * Receive each live record as it is stored.
+     */
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, Arc<Record>)> {
+        self.0.lock().unwrap().events.subscribe()
+    }
+
+    /**
+     * Return every sensor from which we have received a record.
+     */
+    pub fn seen(&self) -> BTreeMap<String, Seen> {
+        self.0.lock().unwrap().seen.clone()
+    }
+
+    /**
+     * Return the message distributions for each sensor, if enabled.
+     */
+    pub fn distributions(&self) -> BTreeMap<String, Distributions> {
+        self.0.lock().unwrap().distributions.clone()
+    }
+
+    pub fn reset_extremes(&self) {
+        self.0.lock().unwrap().extremes.clear();
+    }
+
+    /**
+     * Return the history of each sensor, oldest first, if so configured.
+     */
+    pub fn history(&self) -> BTreeMap<String, Vec<HistoryEntry>> {
+        let l = self.0.lock().unwrap();
+        let Some(period) = l.config.history() else {
+            return Default::default();
+        };
+
+        l.history
+            .iter()
+            .map(|(id, h)| (id.clone(), h.entries(&l.config, period)))
+            .filter(|(_, h)| !h.is_empty())
+            .collect()
+    }
+
+    /**
+     * Return the temperature samples received for this sensor within the
+     * retention window, oldest first.
+     */
+    pub fn recent(&self, id: &str) -> Vec<Sample> {
+        self.0
+            .lock()
+            .unwrap()
+            .recent
+            .get(id)
+            .map(|r| r.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Source {
+    pub fn new(
+        log: Logger,
+        name: String,
+        sensors: Sensors,
+        format: Format,
+    ) -> Source {
+        Source(Arc::new(SourceInner {
+            log,
+            name,
+            sensors,
+            state: Mutex::new(SourceState {
+                up: false,
+                stats: Default::default(),
+                decoder: Decoder::new(format),
+            }),
+        }))
+    }
+
+    /**
+     * A name for the input source, for use in metrics and log messages.
+     */
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.0.state.lock().unwrap().stats.clone()
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut Stats)) {
+        f(&mut self.0.state.lock().unwrap().stats)
+    }
+
+    /**
+     * Is the input source currently open and being read?
+     */
+    pub fn up(&self) -> bool {
+        self.0.state.lock().unwrap().up
+    }
+
+    pub fn set_up(&self, up: bool) {
+        self.0.state.lock().unwrap().up = up;
+    }
+
+    /**
+     * Process a line of input, discarding any line that was too long.
+     */
+    pub fn ingest_line(&self, line: Line) {
+        match line {
+            Line::Complete(l) => self.ingest(l),
+            Line::TooLong => {
+                self.update_stats(|st| {
+                    st.lines += 1;
+                    st.parse_errors += 1;
+                });
+                warn!(self.0.log, "{} discarded overlong line", self.0.name);
+            }
+        }
+    }
+
+    /**
+     * Process a single line of output from rtl_433.
+     */
+    pub fn ingest(&self, line: &[u8]) {
+        let i = &self.0;
+        let c = clean(line);
+
+        let mut st = i.state.lock().unwrap();
+        let res = st.decoder.decode(&c.line);
+        st.stats.lines += 1;
+        if c.crlf {
+            st.stats.crlf += 1;
+        }
+        if c.invalid_utf8 {
+            st.stats.invalid_utf8 += 1;
+        }
+        match res {
+            Ok(records) => {
+                for fields in records {
+                    let res = from_fields(fields);
+                    self.accept(&mut st, res);
+                }
+            }
+            Err(e) => self.accept(&mut st, Err(e)),
+        }
+    }
+
+    /**
+     * Process any record held by the decoder because it cannot yet tell
+     * whether the record is complete; e.g., when we reach the end of the
+     * input.
+     */
+    pub fn flush(&self) {
+        let mut st = self.0.state.lock().unwrap();
+        if let Some(fields) = st.decoder.flush() {
+            let res = from_fields(fields);
+            self.accept(&mut st, res);
+        }
+    }
+
+    fn accept(
+        &self,
+        st: &mut SourceState,
+        res: Result<Option<(String, Record)>>,
+    ) {
+        let i = &self.0;
+        match res {
+            Ok(Some((id, r))) => {
+                let mut l = i.sensors.0.lock().unwrap();
+                if l.spike(&id, &r) {
+                    st.stats.spikes += 1;
+                    warn!(
+                        i.log,
+                        "discarding spike from {id:?}: temperature {:?}",
+                        r.temperature(),
+                    );
+                    return;
+                }
+                *st.stats.records.entry(r.model.clone()).or_default() += 1;
+                if l.config.sensor(&id).is_none()
+                    && l.config.schema(&r.model).is_some()
+                {
+                    *st.stats.unknown.entry(id.clone()).or_default() += 1;
+                }
+                l.record(id, r, true);
+                st.stats.last_record = Some(Instant::now());
+            }
+            Ok(None) => (),
+            Err(e) => {
+                st.stats.parse_errors += 1;
+                warn!(i.log, "{} parse error: {e}", i.name);
+            }
+        }
+    }
+}
+
+impl SdrTail {
+    pub fn new(
+        log: Logger,
+        file: PathBuf,
+        sensors: Sensors,
+        checkpoint: Option<Arc<Checkpoint>>,
+        format: Format,
+    ) -> SdrTail {
+        let name = if is_stdin(&file) {
+            "stdin".to_string()
+        } else {
+            format!("file:{}", file.display())
+        };
+        let sdr = SdrTail(Arc::new(Inner {
+            log: log.clone(),
+            file,
+            sensors: sensors.clone(),
+            source: Source::new(log, name, sensors, format),
+            format,
+            checkpoint,
+            stop: watch::Sender::new(false),
+        }));
+
+        tokio::spawn(sdrtail_task_noerr(sdr.clone()));
+
+        sdr
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.0.source
+    }
+
+    /**
+     * Stop following the file; e.g., because it has been removed.  The tail
+     * task exits the next time it reaches the end of the file, or immediately
+     * if it is already waiting there.
+     */
+    pub fn stop(&self) {
+        self.0.stop.send_replace(true);
+    }
+}
+
+struct Inner {
+    log: Logger,
+    file: PathBuf,
+    sensors: Sensors,
+    source: Source,
+    format: Format,
+    checkpoint: Option<Arc<Checkpoint>>,
+    stop: watch::Sender<bool>,
+}
+
+impl Inner {
+    fn stopped(&self) -> bool {
+        *self.stop.borrow()
+    }
 }
 
-#[derive(Clone, Deserialize, Debug)]
-#[allow(unused)]
-#[allow(non_snake_case)]
-pub struct RecordAcuriteTower {
-    time: String,
-    model: String,
-    id: u64,
-    channel: String,
-    pub battery_ok: i64,
-    pub temperature_C: f32,
-    pub humidity: f32,
-    mic: String,
+struct SourceInner {
+    log: Logger,
+    name: String,
+    sensors: Sensors,
+    state: Mutex<SourceState>,
+}
+
+struct SourceState {
+    up: bool,
+    stats: Stats,
+    decoder: Decoder,
+}
+
+struct Locked {
+    config: Arc<Config>,
+    /*
+     * The most recent record from each sensor.  The map is replaced, rather
+     * than modified, while a snapshot of it is held elsewhere; see values().
+     */
+    current: Arc<BTreeMap<String, Arc<Record>>>,
+    recent: BTreeMap<String, VecDeque<Sample>>,
+    extremes: BTreeMap<String, Extremes>,
+    /*
+     * The local date on which we began tracking the current extremes:
+     */
+    extremes_day: NaiveDate,
+    distributions: BTreeMap<String, Distributions>,
+    seen: BTreeMap<String, Seen>,
+    /*
+     * If so configured, the live records from each sensor within the history
+     * period:
+     */
+    history: BTreeMap<String, History>,
+    /*
+     * Each live record, with the ID of its sensor, is sent to any subscriber
+     * as it is stored:
+     */
+    events: broadcast::Sender<(String, Arc<Record>)>,
+}
+
+/*
+ * The number of records a slow subscriber may fall behind before it begins to
+ * miss them:
+ */
+const EVENTS_MAX: usize = 256;
+
+/**
+ * Counters that describe the health of an input source, for self-monitoring.
+ */
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub lines: u64,
+    pub parse_errors: u64,
+    /*
+     * Lines that ended in a carriage return, or that contained invalid UTF-8
+     * which was replaced before parsing:
+     */
+    pub crlf: u64,
+    pub invalid_utf8: u64,
+    pub reopens: u64,
+    pub offset: u64,
+    pub records: BTreeMap<String, u64>,
+    /*
+     * Records discarded by the spike filter:
+     */
+    pub spikes: u64,
+    /*
+     * Records, by sensor ID, from sensors of a known model that are not
+     * mapped to a location in the configuration:
+     */
+    pub unknown: BTreeMap<String, u64>,
+    /*
+     * When the most recent record was accepted:
+     */
+    pub last_record: Option<Instant>,
 }
 
-fn parse(buf: &[u8]) -> Result<Option<RecordAcuriteTower>> {
-    let rb: RecordBase = serde_json::from_slice(buf)?;
-    if rb.model != "Acurite-Tower" {
+impl Locked {
+    /*
+     * Drop any sensors from which we have not received a record within the
+     * configured expiry period, so that a sensor that has stopped
+     * transmitting disappears from the output rather than reporting its last
+     * value indefinitely.
+     */
+    fn expire(&mut self) {
+        let Some(expire) = self.config.expire() else {
+            return;
+        };
+
+        let now = Instant::now();
+        let live = |r: &Arc<Record>| {
+            now.saturating_duration_since(r.received) <= expire
+        };
+        if !self.current.values().all(live) {
+            Arc::make_mut(&mut self.current).retain(|_, r| live(r));
+        }
+
+        let current = &self.current;
+        self.recent.retain(|id, _| current.contains_key(id));
+        self.extremes.retain(|id, _| current.contains_key(id));
+        self.distributions.retain(|id, _| current.contains_key(id));
+        self.history.retain(|id, _| current.contains_key(id));
+    }
+
+    /*
+     * If so configured, begin tracking new extremes at local midnight.
+     */
+    fn rollover(&mut self) {
+        let today = Local::now().date_naive();
+        if self.config.extremes_daily && today != self.extremes_day {
+            self.extremes.clear();
+        }
+        self.extremes_day = today;
+    }
+
+    /*
+     * Determine whether a record should be discarded by the spike filter.
+     */
+    fn spike(&self, id: &str, r: &Record) -> bool {
+        let Some(sf) = &self.config.spike_filter else {
+            return false;
+        };
+        let Some(old) = self.current.get(id) else {
+            return false;
+        };
+        let (Some(prev), Some(new)) =
+            (old.field("temperature_C"), r.field("temperature_C"))
+        else {
+            return false;
+        };
+
+        let age = r.received.saturating_duration_since(old.received);
+        age <= Duration::from_secs(sf.seconds) && (new - prev).abs() > sf.delta
+    }
+
+    /*
+     * Store a new record from a sensor.  Records that are not live (i.e.,
+     * historical records read at startup) update the latest values, but are
+     * not used for rate-of-change alarms or temperature extremes.
+     */
+    fn record(&mut self, id: String, mut r: Record, live: bool) {
+        let old = self.current.get(&id).filter(|old| old.model == r.model);
+        r.messages += old.map(|old| old.messages).unwrap_or(0);
+
+        let now = Utc::now();
+        self.seen
+            .entry(id.clone())
+            .and_modify(|s| {
+                s.model.clone_from(&r.model);
+                s.last = now;
+                s.messages += 1;
+            })
+            .or_insert_with(|| Seen {
+                model: r.model.clone(),
+                first: now,
+                last: now,
+                messages: 1,
+            });
+
         /*
-         * We don't currently know what to do with other types of devices.
+         * Update the exponentially weighted moving average for any smoothed
+         * fields.  This must be done before merging, so that a value carried
+         * over from a previous message is not counted twice.
          */
-        return Ok(None);
+        for f in self.config.fields_for(&r.model) {
+            let Some(n) = f.smooth else {
+                continue;
+            };
+
+            let prev = old.and_then(|old| old.smoothed.get(&f.metric).copied());
+            let v = match (prev, r.field(&f.path)) {
+                (Some(prev), Some(new)) => {
+                    let alpha = 2.0 / (n as f64 + 1.0);
+                    prev + alpha * (new - prev)
+                }
+                (None, Some(new)) => new,
+                (Some(prev), None) => prev,
+                (None, None) => continue,
+            };
+            r.smoothed.insert(f.metric.clone(), v);
+        }
+
+        if let Some(old) = old {
+            if self.config.schema(&r.model).is_some_and(|s| s.merge) {
+                for (k, v) in old.fields.iter() {
+                    if !r.fields.contains_key(k) {
+                        r.fields.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+
+        for f in self.config.fields_for(&r.model) {
+            if f.wrap.is_none() && !f.resets {
+                continue;
+            }
+            let Some(new) = r.field(&f.path) else {
+                continue;
+            };
+
+            /*
+             * Accumulate the change since the previous message into our own
+             * monotonic total.
+             */
+            let total = old
+                .and_then(|old| {
+                    let prev = old.field(&f.path)?;
+                    let total = old.totals.get(&f.path)?;
+                    Some(total + counter_increase(prev, new, f.wrap))
+                })
+                .unwrap_or(0.0);
+            r.totals.insert(f.path.clone(), total);
+        }
+
+        let window = self.config.window();
+        if let Some(temperature) =
+            r.temperature().filter(|_| live && !window.is_zero())
+        {
+            let now = Instant::now();
+            let recent = self.recent.entry(id.clone()).or_default();
+            while let Some(s) = recent.front() {
+                if now.saturating_duration_since(s.when) <= window {
+                    break;
+                }
+                recent.pop_front();
+            }
+            recent.push_back(Sample { when: now, temperature });
+        }
+
+        if live && self.config.histograms {
+            let d = self
+                .distributions
+                .entry(id.clone())
+                .or_insert_with(Distributions::new);
+            if let Some(snr) = r.field("snr") {
+                d.snr.observe(snr);
+            }
+            if let Some(old) = old {
+                let interval =
+                    r.received.saturating_duration_since(old.received);
+                d.interval.observe(interval.as_secs_f64());
+            }
+        }
+
+        if let Some(t) = r.field("temperature_C").filter(|_| live) {
+            self.rollover();
+            self.extremes
+                .entry(id.clone())
+                .and_modify(|x| {
+                    x.min = x.min.min(t);
+                    x.max = x.max.max(t);
+                })
+                .or_insert(Extremes { min: t, max: t });
+        }
+
+        let r = Arc::new(r);
+        if let Some(period) = self.config.history().filter(|_| live) {
+            let h = self.history.entry(id.clone()).or_default();
+            h.records.push_back(Arc::clone(&r));
+            h.prune(&self.config, period);
+        }
+        if live {
+            /*
+             * It does not matter if there are no subscribers.
+             */
+            self.events.send((id.clone(), Arc::clone(&r))).ok();
+        }
+        Arc::make_mut(&mut self.current).insert(id, r);
     }
+}
 
-    Ok(Some(serde_json::from_slice(buf)?))
+/*
+ * When we reach the end of a data file, we wait for the logger to write more
+ * to it.  Where the operating system provides file change notification
+ * (inotify on Linux, event ports on illumos), we use it to wake up as soon as
+ * the file changes; otherwise, we just poll at the configured interval.  Even
+ * with notification we wake periodically, so that we notice a replaced file or
+ * a request to stop regardless of which events are delivered.
+ */
+struct FileWatch {
+    #[cfg(any(target_os = "linux", target_os = "illumos"))]
+    os: Option<OsWatch>,
+    poll: Duration,
 }
 
-impl SdrTail {
-    pub fn new(log: Logger, file: PathBuf) -> Result<SdrTail> {
-        let sdr = SdrTail(Arc::new(Inner {
-            log,
-            file,
-            locked: Mutex::new(Locked { current: Default::default() }),
-        }));
+#[cfg(any(target_os = "linux", target_os = "illumos"))]
+enum OsWatch {
+    Idle(watch_os::Watch),
+    /*
+     * While we wait, the watch belongs to the task on the blocking thread
+     * pool, which returns it when it is done.
+     */
+    Waiting(tokio::task::JoinHandle<std::io::Result<watch_os::Watch>>),
+}
 
-        let sdr0 = sdr.clone();
-        std::thread::Builder::new()
-            .name("sdrtail".into())
-            .spawn(|| sdrtail_thread_noerr(sdr0))?;
+const WATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+ * How long a data file must be idle before we process a record that is held
+ * by the decoder because it cannot yet tell whether the record is complete.
+ */
+const FLUSH_IDLE: Duration = Duration::from_secs(1);
+
+impl FileWatch {
+    fn new(log: &Logger, file: &Path, poll: Duration) -> FileWatch {
+        #[cfg(any(target_os = "linux", target_os = "illumos"))]
+        {
+            match watch_os::Watch::new(file) {
+                Ok(w) => FileWatch { os: Some(OsWatch::Idle(w)), poll },
+                Err(e) => {
+                    warn!(log, "file {file:?}: polling for changes: {e}");
+                    FileWatch { os: None, poll }
+                }
+            }
+        }
 
-        Ok(sdr)
+        #[cfg(not(any(target_os = "linux", target_os = "illumos")))]
+        {
+            let _ = (log, file);
+            FileWatch { poll }
+        }
     }
 
-    pub fn values(&self) -> Vec<(String, RecordAcuriteTower)> {
-        self.0
-            .locked
-            .lock()
-            .unwrap()
-            .current
-            .iter()
-            .map(|(a, b)| (a.clone(), b.clone()))
-            .collect()
+    /*
+     * Waiting for notification is a blocking operation, so we do it on the
+     * blocking thread pool.  This must be cancel-safe, as the tail waits in a
+     * select!() with other events: if we are cancelled, the blocking task
+     * keeps running, and the next call waits for that task rather than
+     * starting another, so that the watch is not lost.
+     */
+    async fn wait(&mut self) {
+        #[cfg(any(target_os = "linux", target_os = "illumos"))]
+        if let Some(os) = self.os.take() {
+            let os = self.os.insert(match os {
+                OsWatch::Idle(mut w) => {
+                    OsWatch::Waiting(tokio::task::spawn_blocking(move || {
+                        w.wait(WATCH_TIMEOUT).map(|_| w)
+                    }))
+                }
+                os @ OsWatch::Waiting(_) => os,
+            });
+            let OsWatch::Waiting(task) = os else {
+                unreachable!();
+            };
+            match task.await {
+                Ok(Ok(w)) => {
+                    self.os = Some(OsWatch::Idle(w));
+                    return;
+                }
+                _ => self.os = None,
+            }
+        }
+
+        tokio::time::sleep(self.poll).await;
     }
 }
 
-struct Inner {
-    log: Logger,
-    file: PathBuf,
-    locked: Mutex<Locked>,
+#[cfg(target_os = "linux")]
+mod watch_os {
+    use std::{
+        ffi::CString,
+        io::{Error, Result},
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+        time::Duration,
+    };
+
+    pub struct Watch {
+        fd: OwnedFd,
+    }
+
+    impl Watch {
+        pub fn new(file: &Path) -> Result<Watch> {
+            let path = CString::new(file.as_os_str().as_bytes())?;
+
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            let mask = libc::IN_MODIFY
+                | libc::IN_ATTRIB
+                | libc::IN_CLOSE_WRITE
+                | libc::IN_MOVE_SELF
+                | libc::IN_DELETE_SELF;
+            if unsafe {
+                libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask)
+            } < 0
+            {
+                return Err(Error::last_os_error());
+            }
+
+            Ok(Watch { fd })
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ms = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+            if unsafe { libc::poll(&mut pfd, 1, ms) } < 0 {
+                let e = Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+
+            if pfd.revents & libc::POLLIN != 0 {
+                /*
+                 * Discard the pending events; we only need to know that
+                 * something happened.
+                 */
+                let mut buf = [0u8; 4096];
+                if unsafe {
+                    libc::read(
+                        self.fd.as_raw_fd(),
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                    )
+                } < 0
+                {
+                    return Err(Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        }
+    }
 }
 
-struct Locked {
-    current: BTreeMap<String, RecordAcuriteTower>,
+#[cfg(target_os = "illumos")]
+mod watch_os {
+    use std::{
+        ffi::CString,
+        io::{Error, ErrorKind, Result},
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::{ffi::OsStrExt, fs::MetadataExt},
+        },
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    /*
+     * The file_obj structure and the file event flags from
+     * <sys/port.h>, which are not provided by the libc crate:
+     */
+    #[repr(C)]
+    struct FileObj {
+        fo_atime: libc::timespec,
+        fo_mtime: libc::timespec,
+        fo_ctime: libc::timespec,
+        fo_pad: [libc::uintptr_t; 3],
+        fo_name: *const libc::c_char,
+    }
+
+    const FILE_MODIFIED: libc::c_int = 0x00000002;
+    const FILE_ATTRIB: libc::c_int = 0x00000004;
+    const FILE_DELETE: libc::c_int = 0x00000010;
+    const FILE_RENAME_FROM: libc::c_int = 0x00000040;
+    const FILE_TRUNC: libc::c_int = 0x00100000;
+
+    pub struct Watch {
+        port: OwnedFd,
+        file: PathBuf,
+        /*
+         * The kernel refers to the name for as long as the file object is
+         * associated with the port, so we must keep it alive:
+         */
+        name: CString,
+        fobj: Box<FileObj>,
+    }
+
+    /*
+     * The name pointer in the file object refers to the CString we own, so
+     * the watch may be moved to another thread along with it.
+     */
+    unsafe impl Send for Watch {}
+
+    impl Watch {
+        pub fn new(file: &Path) -> Result<Watch> {
+            let name = CString::new(file.as_os_str().as_bytes())?;
+
+            let port = unsafe { libc::port_create() };
+            if port < 0 {
+                return Err(Error::last_os_error());
+            }
+            let port = unsafe { OwnedFd::from_raw_fd(port) };
+
+            let ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+            let fobj = Box::new(FileObj {
+                fo_atime: ts,
+                fo_mtime: ts,
+                fo_ctime: ts,
+                fo_pad: [0; 3],
+                fo_name: name.as_ptr(),
+            });
+
+            Ok(Watch { port, file: file.to_path_buf(), name, fobj })
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+            /*
+             * Each association delivers at most one event, so we associate
+             * the file again each time we wait.  The kernel reports an event
+             * immediately if the file has changed since the times we pass.
+             */
+            let md = std::fs::metadata(&self.file)?;
+            let ts = |sec: i64, nsec: i64| libc::timespec {
+                tv_sec: sec as libc::time_t,
+                tv_nsec: nsec as libc::c_long,
+            };
+            self.fobj.fo_atime = ts(md.atime(), md.atime_nsec());
+            self.fobj.fo_mtime = ts(md.mtime(), md.mtime_nsec());
+            self.fobj.fo_ctime = ts(md.ctime(), md.ctime_nsec());
+            self.fobj.fo_name = self.name.as_ptr();
+
+            let events = FILE_MODIFIED
+                | FILE_ATTRIB
+                | FILE_DELETE
+                | FILE_RENAME_FROM
+                | FILE_TRUNC;
+            if unsafe {
+                libc::port_associate(
+                    self.port.as_raw_fd(),
+                    libc::PORT_SOURCE_FILE,
+                    &*self.fobj as *const FileObj as libc::uintptr_t,
+                    events,
+                    std::ptr::null_mut(),
+                )
+            } < 0
+            {
+                return Err(Error::last_os_error());
+            }
+
+            let mut pe: libc::port_event = unsafe { std::mem::zeroed() };
+            let mut ts = libc::timespec {
+                tv_sec: timeout.as_secs() as libc::time_t,
+                tv_nsec: timeout.subsec_nanos() as libc::c_long,
+            };
+            if unsafe {
+                libc::port_get(self.port.as_raw_fd(), &mut pe, &mut ts)
+            } < 0
+            {
+                let e = Error::last_os_error();
+                if !matches!(e.raw_os_error(), Some(libc::ETIME | libc::EINTR))
+                    && e.kind() != ErrorKind::Interrupted
+                {
+                    return Err(e);
+                }
+            }
+
+            Ok(())
+        }
+    }
 }
 
-fn sdrtail_thread_noerr(sdr: SdrTail) {
-    let log = &sdr.0.log;
+/*
+ * A data file name of "-" refers to the standard input.
+ */
+fn is_stdin(file: &Path) -> bool {
+    file.as_os_str() == "-"
+}
 
+/*
+ * Records may be piped to us on the standard input, e.g., directly from
+ * rtl_433 or from a captured log file.  There is no point in seeking or in
+ * checking whether the file has been replaced, so we just read each line
+ * until the end of the input.
+ */
+async fn stdin_task(sdr: &SdrTail) -> Result<()> {
+    let i = &sdr.0;
+
+    i.source.set_up(true);
+    read_lines(i, BufReader::new(tokio::io::stdin())).await?;
+    info!(i.log, "end of standard input");
+
+    Ok(())
+}
+
+async fn read_lines(i: &Inner, mut r: impl AsyncBufRead + Unpin) -> Result<()> {
+    let mut lines = LineSplitter::new(i.sensors.config().max_line);
     loop {
-        if let Err(e) = sdrtail_thread(&sdr) {
+        let buf = r.fill_buf().await?;
+        if buf.is_empty() {
+            lines.finish(|l| i.source.ingest_line(l));
+            i.source.flush();
+            return Ok(());
+        }
+
+        let sz = buf.len();
+        lines.split(buf, |l| i.source.ingest_line(l));
+        r.consume(sz);
+    }
+}
+
+/*
+ * Data files are often rotated and compressed; e.g., "rtl433.json" becomes
+ * "rtl433.json.1.gz".  If so configured, we read the most recent rotated
+ * files, oldest first, before following the live file so that sensors that
+ * transmit rarely have a value as soon as we start.  Decompression is
+ * comparatively expensive, so this runs on the blocking thread pool.
+ */
+fn backfill(i: &Inner) {
+    let rotations = i.sensors.config().backfill;
+
+    for n in (1..=rotations).rev() {
+        let plain = PathBuf::from(format!("{}.{n}", i.file.display()));
+        let gz = PathBuf::from(format!("{}.{n}.gz", i.file.display()));
+
+        let res = if gz.exists() {
+            std::fs::File::open(&gz)
+                .and_then(|f| {
+                    let r = std::io::BufReader::new(
+                        flate2::read::MultiGzDecoder::new(f),
+                    );
+                    backfill_lines(i, r)
+                })
+                .map(|n| (gz, n))
+        } else if plain.exists() {
+            std::fs::File::open(&plain)
+                .and_then(|f| backfill_lines(i, std::io::BufReader::new(f)))
+                .map(|n| (plain, n))
+        } else {
+            continue;
+        };
+
+        match res {
+            Ok((path, n)) => {
+                info!(i.log, "backfilled {n} records from {path:?}")
+            }
+            Err(e) => warn!(i.log, "backfill rotation {n}: {e}"),
+        }
+    }
+}
+
+fn backfill_lines(i: &Inner, r: impl BufRead) -> std::io::Result<u64> {
+    let mut count = 0;
+    let mut decoder = Decoder::new(i.format);
+
+    /*
+     * Historical records are not counted in the statistics for the input
+     * source, and any malformed lines are ignored.
+     */
+    let mut accept = |res: Result<Option<(String, Record)>>| {
+        if let Ok(Some((id, r))) = res {
+            let mut l = i.sensors.0.lock().unwrap();
+            if !l.spike(&id, &r) {
+                l.record(id, r, false);
+                count += 1;
+            }
+        }
+    };
+
+    crate::lines::read_lines(r, i.sensors.config().max_line, |line| {
+        if let Line::Complete(line) = line {
+            if let Ok(records) = decoder.decode(&clean(line).line) {
+                records.into_iter().for_each(|f| accept(from_fields(f)));
+            }
+        }
+    })?;
+    if let Some(fields) = decoder.flush() {
+        accept(from_fields(fields));
+    }
+
+    Ok(count)
+}
+
+async fn sdrtail_task_noerr(sdr: SdrTail) {
+    let log = &sdr.0.log;
+
+    if is_stdin(&sdr.0.file) {
+        if let Err(e) = stdin_task(&sdr).await {
+            error!(log, "stdin error: {e}");
+        }
+        sdr.0.source.set_up(false);
+        return;
+    }
+
+    let sdr0 = sdr.clone();
+    if let Err(e) = tokio::task::spawn_blocking(move || backfill(&sdr0.0)).await
+    {
+        error!(log, "backfill error: {e}");
+    }
+
+    let mut stop = sdr.0.stop.subscribe();
+    while !sdr.0.stopped() {
+        if let Err(e) = sdrtail_task(&sdr, &mut stop).await {
             error!(log, "sdrtail error: {e}");
         }
+        sdr.0.source.set_up(false);
 
-        std::thread::sleep(Duration::from_secs(2));
+        tokio::select! {
+            _ = tokio::time::sleep(sdr.0.sensors.config().reopen_delay()) => (),
+            _ = stop.changed() => (),
+        }
     }
+
+    info!(log, "stopped following {:?}", sdr.0.file);
 }
 
-fn sdrtail_thread(sdr: &SdrTail) -> Result<()> {
+async fn sdrtail_task(
+    sdr: &SdrTail,
+    stop: &mut watch::Receiver<bool>,
+) -> Result<()> {
     let i = &sdr.0;
     let log = &i.log;
 
     /*
      * Attempt to open the file.
      */
-    let (mut f, md) = match std::fs::File::open(&i.file) {
+    let (f, md) = match tokio::fs::File::open(&i.file).await {
         Ok(f) => {
-            let md = f.metadata()?;
+            let md = f.metadata().await?;
             (f, md)
         }
         Err(e) => bail!("open {:?}: {e}", i.file),
     };
 
+    if md.file_type().is_fifo() {
+        /*
+         * A named pipe cannot be seeked, and the writer may close and reopen
+         * it at will.  Read from it until the writer closes it, and then
+         * open it again to wait for the next writer.
+         */
+        info!(log, "path {:?} is a named pipe", i.file);
+        i.source.set_up(true);
+        read_lines(i, BufReader::new(f)).await?;
+        info!(log, "writer closed named pipe {:?}", i.file);
+        return Ok(());
+    }
+
     /*
      * Store the original device/inode numbers so that we can tell if the file
      * has been replaced.
@@ -119,37 +1802,79 @@ fn sdrtail_thread(sdr: &SdrTail) -> Result<()> {
     let dev = md.dev();
     let ino = md.ino();
     info!(log, "path {:?} has dev {dev:X} inode {ino:X}", i.file);
+    i.source.set_up(true);
 
-    let mut pos = if md.len() > 16 * 1024 {
+    let config = i.sensors.config();
+    let checkpoint = i
+        .checkpoint
+        .as_ref()
+        .and_then(|c| c.position(&i.file))
+        .filter(|p| p.dev == dev && p.ino == ino && p.offset <= md.len());
+
+    let mut lines = LineSplitter::new(config.max_line);
+    let mut pos = if let Some(p) = checkpoint {
+        info!(log, "resuming at checkpoint offset {}", p.offset);
+        p.offset
+    } else if md.len() > config.tail_window {
         /*
-         * Seek to within 16K of the end of the file.
+         * Seek to within the configured window of the end of the file.  We
+         * have most likely landed in the middle of a record, so we back up
+         * one byte and discard everything up to and including the first
+         * newline.  If we did land at the start of a line, all we discard is
+         * the newline that ended the previous one.
          */
-        let pos = md.len().checked_sub(16 * 1024).unwrap();
+        let pos = md.len().checked_sub(config.tail_window + 1).unwrap();
         info!(log, "file size is {}, picking up at {pos}", md.len());
+        lines.skip_partial();
         pos
     } else {
         info!(log, "file size is {}, starting at beginning", md.len());
         0
     };
 
-    f.seek(std::io::SeekFrom::Start(pos))?;
+    let mut f = BufReader::with_capacity(config.read_buffer, f);
+    f.seek(std::io::SeekFrom::Start(pos)).await?;
 
     /*
      * Now, read data until we hit EOF, splitting it into lines to process.
      */
-    let mut s = Vec::new();
-    let mut buf = vec![0u8; 16 * 1024];
+    let mut watch = FileWatch::new(log, &i.file, config.poll_interval());
+    let mut last_read: Option<Instant> = None;
     loop {
-        let sz = f.read(&mut buf)?;
-        pos = pos.checked_add(sz.try_into().unwrap()).unwrap();
+        let buf = f.fill_buf().await?;
+        let sz = buf.len();
 
         if sz == 0 {
             /*
              * This is EOF, but the SDR data logger should continue to write
              * more soon.  Take this opportunity to confirm that the file
              * has not changed.
+             *
+             * Reaching EOF does not mean that a record is complete, as the
+             * logger may be part way through writing one that spans several
+             * lines.  A record held by the decoder is only processed once the
+             * file has been idle for a while, or is closed.
              */
-            if let Ok(md) = std::fs::metadata(&i.file) {
+            if last_read.is_some_and(|t| t.elapsed() >= FLUSH_IDLE) {
+                i.source.flush();
+                last_read = None;
+            }
+            if i.stopped() {
+                i.source.flush();
+                return Ok(());
+            }
+
+            /*
+             * Record the position of the end of the last complete line; any
+             * partial line will be read again after a restart.  If we are
+             * discarding the rest of a line, there is no such position.
+             */
+            if let (Some(c), Some(n)) = (&i.checkpoint, lines.pending()) {
+                let offset = pos - n as u64;
+                c.update(&i.file, Position { dev, ino, offset });
+            }
+
+            if let Ok(md) = tokio::fs::metadata(&i.file).await {
                 let mut new_file = false;
                 if md.dev() != dev {
                     info!(
@@ -179,42 +1904,171 @@ fn sdrtail_thread(sdr: &SdrTail) -> Result<()> {
                 }
                 if new_file {
                     info!(log, "reopening file {:?}", i.file);
+                    i.source.flush();
+                    i.source.update_stats(|st| st.reopens += 1);
                     return Ok(());
                 }
             }
 
             /*
-             * Wait and try again!  We could use some kind of file event
-             * notification but ... I am already in my pyjamas.
+             * Wait for the file to change and try again!
              */
-            std::thread::sleep(Duration::from_secs(1));
+            let idle = async {
+                match last_read {
+                    Some(t) => {
+                        tokio::time::sleep_until((t + FLUSH_IDLE).into()).await
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = watch.wait() => (),
+                _ = stop.changed() => (),
+                _ = idle => (),
+            }
             continue;
         }
 
-        for b in &buf[0..sz] {
-            if *b == b'\n' {
-                /*
-                 * Process whatever we have in the accumulator...
-                 */
-                match parse(&s) {
-                    Ok(Some(r)) => {
-                        let mut l = i.locked.lock().unwrap();
-                        let id = format!(
-                            "{}-{:08}-{}",
-                            r.model.to_lowercase(),
-                            r.id,
-                            r.channel.to_lowercase()
-                        );
-                        l.current.insert(id, r);
-                    }
-                    Ok(None) => (),
-                    Err(e) => warn!(log, "file {:?} parse error: {e}", i.file),
-                }
+        lines.split(buf, |l| i.source.ingest_line(l));
+        f.consume(sz);
+        last_read = Some(Instant::now());
+
+        pos = pos.checked_add(sz.try_into().unwrap()).unwrap();
+        i.source.update_stats(|st| st.offset = pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn time() {
+        let local = |s| {
+            let naive =
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+                    .unwrap();
+            Local.from_local_datetime(&naive).earliest().unwrap().to_utc()
+        };
+        let utc = |s| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+
+        let cases = [
+            (json!("2024-03-01 12:34:56"), Some(local("2024-03-01 12:34:56"))),
+            (json!("2024-03-01T12:34:56"), Some(local("2024-03-01 12:34:56"))),
+            (
+                json!("2024-03-01 12:34:56.250"),
+                Some(local("2024-03-01 12:34:56.250")),
+            ),
+            (
+                json!("2024-03-01T12:34:56+1100"),
+                Some(utc("2024-03-01T01:34:56Z")),
+            ),
+            (
+                json!("2024-03-01 12:34:56-0500"),
+                Some(utc("2024-03-01T17:34:56Z")),
+            ),
+            (json!("2024-03-01T12:34:56Z"), Some(utc("2024-03-01T12:34:56Z"))),
+            (json!("1709257496"), Some(utc("2024-03-01T01:44:56Z"))),
+            (json!("1709257496.123"), Some(utc("2024-03-01T01:44:56.123Z"))),
+            (json!(1709257496), Some(utc("2024-03-01T01:44:56Z"))),
+            (json!(1709257496.5), Some(utc("2024-03-01T01:44:56.5Z"))),
+            (json!(1709257496.123), Some(utc("2024-03-01T01:44:56.123Z"))),
+            (json!(1.7e9), Some(utc("2023-11-14T22:13:20Z"))),
+            (json!(-1), None),
+            (json!("12:34:56"), None),
+            (json!(true), None),
+        ];
+
+        for (time, expected) in cases {
+            assert_eq!(parse_time(&time), expected, "{time}");
+        }
+    }
 
-                s.clear();
-            } else {
-                s.push(*b);
+    #[test]
+    fn units() {
+        let cases = [
+            (
+                json!({"temperature_F": 70.7, "humidity": 45}),
+                json!({"temperature_C": 21.5, "humidity": 45}),
+            ),
+            (json!({"temperature_F": -40}), json!({"temperature_C": -40.0})),
+            (
+                json!({"wind_avg_mi_h": 10.0, "wind_max_mph": 25}),
+                json!({"wind_avg_m_s": 4.4704, "wind_max_m_s": 11.176}),
+            ),
+            (json!({"wind_avg_km_h": 36.0}), json!({"wind_avg_m_s": 10.0})),
+            (
+                json!({"rain_in": 0.5, "rain_rate_in_h": 0.1}),
+                json!({"rain_mm": 12.7, "rain_rate_mm_h": 2.54}),
+            ),
+            (
+                json!({"pressure_hPa": 1013.25, "battery_mV": 3000}),
+                json!({"pressure_Pa": 101325.0, "battery_V": 3.0}),
+            ),
+            /*
+             * A value reported in the canonical unit is kept:
+             */
+            (
+                json!({"temperature_C": 21.0, "temperature_F": 70.0}),
+                json!({"temperature_C": 21.0}),
+            ),
+            /*
+             * Values that are not numbers are left alone:
+             */
+            (
+                json!({"temperature_F": "n/a", "model": "Acurite-Tower"}),
+                json!({"temperature_F": "n/a", "model": "Acurite-Tower"}),
+            ),
+        ];
+
+        for (fields, expected) in cases {
+            let Value::Object(mut fields) = fields else { unreachable!() };
+            normalize_units(&mut fields);
+
+            let Value::Object(expected) = expected else { unreachable!() };
+            assert_eq!(
+                fields.keys().collect::<Vec<_>>(),
+                expected.keys().collect::<Vec<_>>(),
+            );
+            for (k, v) in expected {
+                match (v.as_f64(), fields[&k].as_f64()) {
+                    (Some(e), Some(n)) => assert!((e - n).abs() < 1e-9, "{k}"),
+                    _ => assert_eq!(fields[&k], v, "{k}"),
+                }
             }
         }
     }
+
+    #[cfg(any(target_os = "linux", target_os = "illumos"))]
+    #[tokio::test]
+    async fn file_watch_cancelled() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir()
+            .join(format!("tempexporter-watch-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+
+        /*
+         * Poll so rarely that only the watch could wake us in time.
+         */
+        let mut watch = FileWatch::new(&log, &path, Duration::from_secs(60));
+        assert!(watch.os.is_some());
+
+        /*
+         * Abandon a wait, as the tail does when the file has been idle for
+         * long enough to flush the decoder.  The watch must survive.
+         */
+        let wait = watch.wait();
+        assert!(tokio::time::timeout(Duration::from_millis(100), wait)
+            .await
+            .is_err());
+        assert!(watch.os.is_some());
+
+        std::fs::write(&path, "{}\n").unwrap();
+        let res =
+            tokio::time::timeout(Duration::from_secs(3), watch.wait()).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(res.is_ok());
+        assert!(matches!(watch.os, Some(OsWatch::Idle(_))));
+    }
 }
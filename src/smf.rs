@@ -0,0 +1,103 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Support for running as an illumos SMF service.  With "--smf", we log in
+ * the bunyan format to standard error, which SMF collects in the service log,
+ * and exit with a status from which SMF can tell a problem with the
+ * configuration (which will not be fixed by restarting us) from any other
+ * failure.
+ */
+
+use std::fmt;
+
+/*
+ * From <libscf.h>:
+ */
+pub const EXIT_ERR_FATAL: i32 = 95;
+pub const EXIT_ERR_CONFIG: i32 = 96;
+
+/**
+ * An error in the configuration file or on the command line.
+ */
+#[derive(Debug)]
+pub struct ConfigError(pub anyhow::Error);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/**
+ * Produce a service manifest with a start method that runs this program with
+ * the given arguments.
+ */
+pub fn manifest(args: &[String]) -> String {
+    let exec = args.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ");
+
+    format!(
+        "<?xml version='1.0'?>
+<!DOCTYPE service_bundle SYSTEM '/usr/share/lib/xml/dtd/service_bundle.dtd.1'>
+<service_bundle type='manifest' name='export'>
+  <service name='site/temperature-exporter' type='service' version='0'>
+    <create_default_instance enabled='true'/>
+
+    <dependency name='multi-user-server' grouping='require_all'
+      restart_on='none' type='service'>
+      <service_fmri value='svc:/milestone/multi-user-server:default'/>
+    </dependency>
+
+    <exec_method name='start' type='method'
+      exec='{}'
+      timeout_seconds='10'/>
+    <exec_method name='stop' type='method' exec=':kill' timeout_seconds='10'/>
+
+    <property_group name='startd' type='framework'>
+      <propval name='duration' type='astring' value='child'/>
+    </property_group>
+
+    <stability value='Unstable'/>
+
+    <template>
+      <common_name>
+        <loctext xml:lang='C'>Temperature exporter from SDR data file</loctext>
+      </common_name>
+    </template>
+  </service>
+</service_bundle>
+",
+        escape(&exec),
+    )
+}
+
+/*
+ * The start method is interpreted by the shell, so any argument that is not
+ * made up only of characters that are safe there must be quoted.
+ */
+fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\'' => out.push_str("&apos;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,91 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use hyper::{Client, Uri};
+use slog::{info, warn, Logger};
+
+use crate::config::ConfigStream;
+use crate::format::Format;
+use crate::sdr::{Sensors, Source};
+
+/*
+ * The HTTP server built into rtl_433 ("-F http") provides records either as
+ * line-delimited JSON ("/stream") or as server-sent events ("/events"), in
+ * which each record appears on a line of the form "data: {...}".  Other
+ * lines in an event stream (comments, event names, and the blank lines that
+ * separate events) carry no records.
+ */
+fn record(line: &[u8]) -> Option<&[u8]> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_prefix(b"data:").unwrap_or(line);
+    let line = line.trim_ascii_start();
+
+    line.starts_with(b"{").then_some(line)
+}
+
+async fn follow(log: &Logger, url: &Uri, source: &Source) -> Result<()> {
+    let res = Client::new().get(url.clone()).await?;
+    if !res.status().is_success() {
+        bail!("GET {url}: {}", res.status());
+    }
+
+    info!(log, "connected to {url}");
+    source.set_up(true);
+
+    let mut body = res.into_body();
+    let mut s = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+
+        let mut rest = &chunk[..];
+        while let Some(n) = memchr::memchr(b'\n', rest) {
+            s.extend_from_slice(&rest[0..n]);
+            if let Some(r) = record(&s) {
+                source.ingest(r);
+            }
+            s.clear();
+
+            rest = &rest[n + 1..];
+        }
+        s.extend_from_slice(rest);
+    }
+
+    bail!("stream from {url} ended");
+}
+
+/**
+ * Follow the stream of records from the HTTP server built into rtl_433,
+ * reconnecting whenever the connection is lost.
+ */
+pub fn start(
+    log: Logger,
+    c: &ConfigStream,
+    sensors: Sensors,
+) -> Result<Source> {
+    let url: Uri = match c.url.parse() {
+        Ok(url) => url,
+        Err(e) => bail!("stream url {:?}: {e}", c.url),
+    };
+
+    let source =
+        Source::new(log.clone(), url.to_string(), sensors, Format::Json);
+
+    let source0 = source.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = follow(&log, &url, &source0).await {
+                warn!(log, "stream error: {e}");
+            }
+            source0.set_up(false);
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    });
+
+    Ok(source)
+}
@@ -0,0 +1,79 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use anyhow::{bail, Result};
+use slog::{info, warn, Logger};
+use tokio::net::UdpSocket;
+
+use crate::config::ConfigSyslog;
+use crate::format::Format;
+use crate::sdr::{Sensors, Source};
+
+/*
+ * rtl_433 sends each record in a separate datagram ("-F syslog:HOST:PORT"),
+ * formatted as an RFC 5424 message; e.g.,
+ *
+ *     <165>1 2024-03-01T12:00:00Z host rtl_433 - - - {"time" : ...}
+ *
+ * We discard everything before the JSON object.
+ */
+fn strip_header(msg: &[u8]) -> Option<&[u8]> {
+    let start = memchr::memchr(b'{', msg)?;
+    let msg = &msg[start..];
+
+    /*
+     * Some senders terminate the message with a newline (or a NUL).
+     */
+    let end = msg
+        .iter()
+        .rposition(|&b| b != b'\n' && b != b'\r' && b != 0)
+        .map(|n| n + 1)
+        .unwrap_or(0);
+    Some(&msg[..end])
+}
+
+/**
+ * Listen for syslog datagrams from rtl_433, feeding each record to the same
+ * parser used for data files.
+ */
+pub async fn start(
+    log: Logger,
+    c: &ConfigSyslog,
+    sensors: Sensors,
+) -> Result<Source> {
+    let sock = match UdpSocket::bind(&c.bind).await {
+        Ok(sock) => sock,
+        Err(e) => bail!("syslog bind {:?}: {e}", c.bind),
+    };
+    info!(log, "listening for syslog datagrams on {:?}", sock.local_addr()?);
+
+    let source = Source::new(
+        log.clone(),
+        format!("syslog:{}", c.bind),
+        sensors,
+        Format::Json,
+    );
+    source.set_up(true);
+
+    let source0 = source.clone();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let (sz, from) = match sock.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(e) => {
+                    warn!(log, "syslog receive: {e}");
+                    continue;
+                }
+            };
+
+            match strip_header(&buf[..sz]) {
+                Some(msg) => source0.ingest(msg),
+                None => warn!(log, "datagram from {from} has no record"),
+            }
+        }
+    });
+
+    Ok(source)
+}
@@ -0,0 +1,143 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+/*
+ * Integration with systemd, without depending on libsystemd: notification of
+ * readiness and watchdog keepalives for "Type=notify" services, and listening
+ * sockets passed to us by socket activation.  Each is a no-op if we were not
+ * started in that way.
+ */
+
+use std::{
+    ffi::OsStr,
+    os::fd::{FromRawFd, RawFd},
+    os::unix::{ffi::OsStrExt, net::UnixDatagram},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+
+use crate::unix::Listener;
+
+/*
+ * Passed file descriptors begin after standard input, output, and error.
+ */
+const LISTEN_FDS_START: RawFd = 3;
+
+/**
+ * Send a state change (e.g., "READY=1") to the service manager.
+ */
+pub fn notify(state: &str) -> Result<()> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let sock = UnixDatagram::unbound()?;
+    let path = path.as_bytes();
+    if let Some(name) = path.strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr =
+                std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            bail!("abstract notification sockets are not supported");
+        }
+    }
+
+    let path = Path::new(OsStr::from_bytes(path));
+    sock.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+/**
+ * If the service manager expects watchdog keepalives from us, the interval
+ * within which each must be sent.
+ */
+pub fn watchdog() -> Option<Duration> {
+    if !for_us("WATCHDOG_PID") {
+        return None;
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|d| !d.is_zero())
+}
+
+/**
+ * Take any listening sockets passed to us by socket activation.
+ */
+pub fn listeners() -> Result<Vec<Listener>> {
+    let ours = std::env::var_os("LISTEN_PID").is_some() && for_us("LISTEN_PID");
+    let n = std::env::var("LISTEN_FDS").ok();
+
+    /*
+     * The variables describe file descriptors that we do not pass on, so
+     * they must not be inherited by any child process.
+     */
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+
+    let Some(n) = n.filter(|_| ours) else {
+        return Ok(Vec::new());
+    };
+    let Ok(n) = n.parse::<RawFd>() else {
+        bail!("invalid LISTEN_FDS {n:?}");
+    };
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + n).map(listener).collect()
+}
+
+/*
+ * Variables that name a process (e.g., "LISTEN_PID") are intended only for
+ * that process, and not for any of its children that inherit them.  If the
+ * variable is absent, the rest are meant for whoever finds them.
+ */
+fn for_us(var: &str) -> bool {
+    match std::env::var(var) {
+        Ok(pid) => pid.parse() == Ok(std::process::id()),
+        Err(_) => true,
+    }
+}
+
+fn listener(fd: RawFd) -> Result<Listener> {
+    /*
+     * Make sure that the socket is not inherited by any child process.
+     */
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        bail!("passed fd {fd}: {}", std::io::Error::last_os_error());
+    }
+
+    let mut ss: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&ss) as libc::socklen_t;
+    if unsafe {
+        libc::getsockname(fd, std::ptr::addr_of_mut!(ss).cast(), &mut len)
+    } != 0
+    {
+        bail!("passed fd {fd}: {}", std::io::Error::last_os_error());
+    }
+
+    match libc::c_int::from(ss.ss_family) {
+        libc::AF_UNIX => {
+            let l =
+                unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+            l.set_nonblocking(true)?;
+            Ok(Listener::Unix(tokio::net::UnixListener::from_std(l)?))
+        }
+        libc::AF_INET | libc::AF_INET6 => {
+            let l = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            l.set_nonblocking(true)?;
+            Ok(Listener::Tcp(tokio::net::TcpListener::from_std(l)?))
+        }
+        other => bail!("passed fd {fd}: unsupported address family {other}"),
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use dropshot::ConfigTls;
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+
+/**
+ * Determine how the HTTP server should use TLS.  If a client CA is
+ * specified, every client must present a certificate signed by that CA;
+ * dropshot has no such option of its own, so we must assemble the complete
+ * rustls configuration ourselves.
+ */
+pub fn config(
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<&Path>,
+) -> Result<ConfigTls> {
+    let Some(client_ca) = client_ca else {
+        return Ok(ConfigTls::AsFile { cert_file: cert, key_file: key });
+    };
+
+    let mut roots = RootCertStore::empty();
+    for ca in certs(client_ca)? {
+        roots.add(ca).map_err(|e| anyhow!("client CA {client_ca:?}: {e}"))?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| anyhow!("client CA {client_ca:?}: {e}"))?;
+
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut reader(&key)?)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| anyhow!("private key {key:?}: {e}"))?;
+    if keys.len() != 1 {
+        bail!("private key {key:?}: expected a single PKCS #8 key");
+    }
+
+    let mut cfg = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs(&cert)?, keys.remove(0).into())
+        .map_err(|e| anyhow!("certificate {cert:?}: {e}"))?;
+    cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(ConfigTls::Dynamic(cfg))
+}
+
+fn reader(path: &Path) -> Result<BufReader<File>> {
+    match File::open(path) {
+        Ok(f) => Ok(BufReader::new(f)),
+        Err(e) => bail!("open {path:?}: {e}"),
+    }
+}
+
+fn certs(
+    path: &Path,
+) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut reader(path)?)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| anyhow!("certificates {path:?}: {e}"))?;
+    if certs.is_empty() {
+        bail!("no certificates found in {path:?}");
+    }
+    Ok(certs)
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::{
+    convert::Infallible,
+    net::{Ipv6Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Result};
+use dropshot::{
+    endpoint, ApiDescription, ConfigDropshot, ConfigTls, HandlerTaskMode,
+    HttpError, HttpResponseUpdatedNoContent, HttpServerStarter, RequestContext,
+    RequestInfo, HEADER_REQUEST_ID,
+};
+use futures::future::BoxFuture;
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response};
+use slog::{info, o, warn, Logger};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+
+use crate::Main;
+
+/*
+ * Dropshot can only serve connections that it accepts from a TCP socket that
+ * it has bound itself.  To serve requests on a Unix domain socket, or on a
+ * socket passed to us by systemd, we accept each connection ourselves and
+ * serve HTTP on it directly with hyper, passing requests to the same handlers
+ * through a router of our own.
+ */
+
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/**
+ * Handles a request from the peer at the given address.
+ */
+pub type Handler = Arc<
+    dyn Fn(Request<Body>, SocketAddr) -> BoxFuture<'static, Response<Body>>
+        + Send
+        + Sync,
+>;
+
+/*
+ * The request context captured from the startup server; see startup().
+ */
+static STARTUP: Mutex<Option<RequestContext<Arc<Main>>>> = Mutex::new(None);
+
+endpoints! {
+    #[endpoint {
+        method = GET,
+        path = "/",
+        unpublished = true,
+    }]
+    pub async fn startup_capture(
+        rc: RequestContext<Arc<Main>>,
+    ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+        STARTUP.lock().unwrap().get_or_insert(rc);
+        Ok(HttpResponseUpdatedNoContent())
+    }
+}
+
+/*
+ * The handlers need the state that dropshot keeps for each server: our
+ * context, and the server configuration, such as the limit on the size of a
+ * request body.  That state cannot be constructed outside of dropshot, and
+ * dropshot 0.10 cannot serve a connection that we have accepted ourselves,
+ * so we obtain it from a server with the same configuration as the others.
+ * That server listens on an ephemeral loopback port only for as long as it
+ * takes us to make a single request of it, before we serve anything else.
+ * Nothing else in the state is consulted as we serve requests: we terminate
+ * TLS with our own acceptor, and log each request with our own logger.
+ *
+ * Building a RequestContext and using the router directly relies on details
+ * of dropshot that are public but not intended for this use, which is why
+ * dropshot is pinned to an exact version in Cargo.toml.
+ */
+async fn startup(
+    cfg: &ConfigDropshot,
+    m: Arc<Main>,
+) -> Result<RequestContext<Arc<Main>>> {
+    let mut api = ApiDescription::new();
+    api.register(startup_capture).map_err(|e| anyhow!(e))?;
+
+    let cfg =
+        ConfigDropshot { bind_address: "127.0.0.1:0".parse()?, ..cfg.clone() };
+    let log = Logger::root(slog::Discard, o!());
+    let server = HttpServerStarter::new(&cfg, api, m, &log)
+        .map_err(|e| anyhow!("startup server failure: {e:?}"))?
+        .start();
+
+    let uri = format!("http://{}/", server.local_addr()).parse()?;
+    let res = hyper::Client::new().get(uri).await;
+    drop(server);
+    res?;
+
+    STARTUP
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| anyhow!("startup server did not handle request"))
+}
+
+/**
+ * Produce a handler that routes requests to the endpoints in the API
+ * description, serving and logging each in the same way as a dropshot server
+ * with the given configuration.
+ */
+pub async fn handler(
+    log: &Logger,
+    cfg: &ConfigDropshot,
+    api: ApiDescription<Arc<Main>>,
+    m: Arc<Main>,
+) -> Result<Handler> {
+    let server = startup(cfg, m).await?.server;
+    let router = Arc::new(api.into_router());
+    let mode = cfg.default_handler_task_mode;
+    let log = log.clone();
+
+    Ok(Arc::new(move |req: Request<Body>, remote_addr| {
+        let server = Arc::clone(&server);
+        let router = Arc::clone(&router);
+        let start = Instant::now();
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let log = log.new(o!(
+            "remote_addr" => remote_addr,
+            "req_id" => request_id.clone(),
+            "method" => req.method().as_str().to_string(),
+            "uri" => req.uri().to_string(),
+        ));
+
+        let handle = async move {
+            let res = match router
+                .lookup_route(req.method(), req.uri().path().into())
+            {
+                Ok(lookup) => {
+                    let rc = RequestContext {
+                        server,
+                        request: RequestInfo::new(&req, remote_addr),
+                        path_variables: lookup.variables,
+                        body_content_type: lookup.body_content_type,
+                        request_id: request_id.clone(),
+                        log: log.clone(),
+                    };
+                    lookup.handler.handle_request(rc, req).await
+                }
+                Err(e) => Err(e),
+            };
+
+            let latency_us = start.elapsed().as_micros();
+            match res {
+                Ok(mut res) => {
+                    info!(log, "request completed";
+                        "response_code" => res.status().as_str(),
+                        "latency_us" => latency_us,
+                    );
+                    if let Ok(v) = request_id.parse() {
+                        res.headers_mut().insert(HEADER_REQUEST_ID, v);
+                    }
+                    res
+                }
+                Err(e) => {
+                    let internal = e.internal_message.clone();
+                    let external = e.external_message.clone();
+                    let res = e.into_response(&request_id);
+                    info!(log, "request completed";
+                        "response_code" => res.status().as_str(),
+                        "latency_us" => latency_us,
+                        "error_message_internal" => internal,
+                        "error_message_external" => external,
+                    );
+                    res
+                }
+            }
+        };
+
+        Box::pin(async move {
+            match mode {
+                HandlerTaskMode::CancelOnDisconnect => handle.await,
+                /*
+                 * The handler runs to completion even if the client
+                 * disconnects.
+                 */
+                HandlerTaskMode::Detached => match tokio::spawn(handle).await {
+                    Ok(res) => res,
+                    Err(e) => std::panic::resume_unwind(e.into_panic()),
+                },
+            }
+        })
+    }))
+}
+
+/**
+ * Produce a TLS acceptor from the same configuration as the HTTP servers.
+ */
+pub fn acceptor(tls: &ConfigTls) -> Result<TlsAcceptor> {
+    let cfg = rustls::ServerConfig::try_from(tls)?;
+    Ok(TlsAcceptor::from(Arc::new(cfg)))
+}
+
+/**
+ * Listen on a Unix domain socket, and serve requests on each connection.
+ */
+pub async fn start(
+    log: Logger,
+    path: &Path,
+    mode: Option<u32>,
+    tls: Option<TlsAcceptor>,
+    handler: Handler,
+) -> Result<()> {
+    remove_stale(&log, path).await?;
+
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(e) => bail!("bind {path:?}: {e}"),
+    };
+    if let Some(mode) = mode {
+        let perms = std::fs::Permissions::from_mode(mode);
+        if let Err(e) = std::fs::set_permissions(path, perms) {
+            bail!("set mode of {path:?}: {e}");
+        }
+    }
+    info!(log, "listening on {path:?}");
+
+    serve(log, Listener::Unix(listener), tls, handler);
+    Ok(())
+}
+
+/**
+ * Serve requests on each connection to a listening socket.
+ */
+pub fn serve(
+    log: Logger,
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    handler: Handler,
+) {
+    tokio::spawn(async move {
+        loop {
+            let res = match &listener {
+                Listener::Unix(l) => {
+                    l.accept().await.map(|(c, _)| match peer_addr(&c) {
+                        Ok(addr) => connection(&log, c, addr, &tls, &handler),
+                        Err(e) => warn!(log, "peer credentials: {e}"),
+                    })
+                }
+                Listener::Tcp(l) => l
+                    .accept()
+                    .await
+                    .map(|(c, addr)| connection(&log, c, addr, &tls, &handler)),
+            };
+            if let Err(e) = res {
+                warn!(log, "accept: {e}");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    });
+}
+
+fn connection<S>(
+    log: &Logger,
+    conn: S,
+    remote_addr: SocketAddr,
+    tls: &Option<TlsAcceptor>,
+    handler: &Handler,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let log = log.clone();
+    let tls = tls.clone();
+    let handler = Arc::clone(handler);
+    let svc = service_fn(move |req| {
+        let res = handler(req, remote_addr);
+        async move { Ok::<_, Infallible>(res.await) }
+    });
+
+    tokio::spawn(async move {
+        let res = match tls {
+            Some(tls) => match tls.accept(conn).await {
+                Ok(conn) => Http::new().serve_connection(conn, svc).await,
+                Err(e) => {
+                    warn!(log, "TLS handshake: {e}";
+                        "remote_addr" => remote_addr,
+                    );
+                    return;
+                }
+            },
+            None => Http::new().serve_connection(conn, svc).await,
+        };
+        if let Err(e) = res {
+            warn!(log, "connection: {e}"; "remote_addr" => remote_addr);
+        }
+    });
+}
+
+/*
+ * A peer on a Unix domain socket has no address, but we need one for the
+ * request log and for rate limiting.  We make one up in the IPv6 discard
+ * prefix (100::/64) from the user ID of the peer, so that each local user is
+ * limited separately.
+ */
+fn peer_addr(conn: &UnixStream) -> std::io::Result<SocketAddr> {
+    let uid = conn.peer_cred()?.uid();
+    let ip = Ipv6Addr::from((0x100u128 << 112) | u128::from(uid));
+    Ok(SocketAddr::new(ip.into(), 0))
+}
+
+/*
+ * A socket left behind by a previous process that did not exit cleanly would
+ * prevent us from binding, so we remove it, but only if nothing is listening
+ * on it any more.
+ */
+async fn remove_stale(log: &Logger, path: &Path) -> Result<()> {
+    let md = match std::fs::symlink_metadata(path) {
+        Ok(md) => md,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => bail!("stat {path:?}: {e}"),
+    };
+    if !md.file_type().is_socket() {
+        bail!("{path:?} exists and is not a socket");
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("{path:?} is in use by another process");
+    }
+
+    info!(log, "removing stale socket {path:?}");
+    if let Err(e) = std::fs::remove_file(path) {
+        bail!("remove {path:?}: {e}");
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright 2024 Oxide Computer Company
+ */
+
+use std::{
+    collections::BTreeMap,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use slog::{info, o, Logger};
+
+use crate::checkpoint::Checkpoint;
+use crate::format::Format;
+use crate::sdr::{SdrTail, Sensors};
+
+/**
+ * A data file named on the command line may be a specific file, a directory
+ * containing data files, or a glob pattern (e.g., "rtl433-*.json").
+ */
+enum Input {
+    File(PathBuf),
+    Pattern(glob::Pattern),
+}
+
+impl Input {
+    fn parse(arg: &str) -> Result<Input> {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            let dir = glob::Pattern::escape(arg.trim_end_matches('/'));
+            return Ok(Input::Pattern(glob::Pattern::new(&format!(
+                "{dir}/*"
+            ))?));
+        }
+
+        if arg.contains(['*', '?', '[']) {
+            return match glob::Pattern::new(arg) {
+                Ok(p) => Ok(Input::Pattern(p)),
+                Err(e) => bail!("invalid pattern {arg:?}: {e}"),
+            };
+        }
+
+        Ok(Input::File(path.to_path_buf()))
+    }
+
+    /**
+     * List the files that currently match this input.  A specific file is
+     * always followed, whether or not it exists yet.
+     */
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        match self {
+            Input::File(path) => Ok(vec![path.clone()]),
+            Input::Pattern(p) => Ok(glob::glob(p.as_str())?
+                .filter_map(|ent| ent.ok())
+                .filter(|path| {
+                    std::fs::metadata(path).is_ok_and(|md| {
+                        md.is_file() || md.file_type().is_fifo()
+                    })
+                })
+                .collect()),
+        }
+    }
+}
+
+/**
+ * The set of data files being followed, each with its own tail task.  When
+ * inputs are directories or patterns, the set changes as matching files
+ * appear and disappear.
+ */
+pub struct Tails {
+    log: Logger,
+    sensors: Sensors,
+    checkpoint: Option<Arc<Checkpoint>>,
+    format: Format,
+    inputs: Vec<Input>,
+    tails: Mutex<BTreeMap<PathBuf, SdrTail>>,
+}
+
+impl Tails {
+    pub fn new(
+        log: Logger,
+        sensors: Sensors,
+        checkpoint: Option<Arc<Checkpoint>>,
+        format: Format,
+        args: &[String],
+    ) -> Result<Tails> {
+        let inputs =
+            args.iter().map(|a| Input::parse(a)).collect::<Result<Vec<_>>>()?;
+
+        let t = Tails {
+            log,
+            sensors,
+            checkpoint,
+            format,
+            inputs,
+            tails: Default::default(),
+        };
+        t.rescan()?;
+        Ok(t)
+    }
+
+    /**
+     * Start following any newly matched files, and stop following any that
+     * no longer match.
+     */
+    pub fn rescan(&self) -> Result<()> {
+        let mut files = Vec::new();
+        for i in self.inputs.iter() {
+            files.extend(i.files()?);
+        }
+
+        let mut tails = self.tails.lock().unwrap();
+
+        tails.retain(|path, t| {
+            if files.contains(path) {
+                return true;
+            }
+            info!(self.log, "file {path:?} no longer present");
+            t.stop();
+            false
+        });
+
+        for file in files {
+            if tails.contains_key(&file) {
+                continue;
+            }
+
+            info!(self.log, "following file {file:?}");
+            let log = self.log.new(o!(
+                "component" => "sdrtail",
+                "file" => file.display().to_string(),
+            ));
+            let checkpoint = self.checkpoint.clone();
+            let t = SdrTail::new(
+                log,
+                file.clone(),
+                self.sensors.clone(),
+                checkpoint,
+                self.format,
+            );
+            tails.insert(file, t);
+        }
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<SdrTail> {
+        self.tails.lock().unwrap().values().cloned().collect()
+    }
+}